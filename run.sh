@@ -1,2 +1,2 @@
 export RUSTFLAGS=-Awarnings
-cargo run -r --features local --bin $1
\ No newline at end of file
+cargo run -r --features local --bin $1 -- "${@:2}"
\ No newline at end of file
@@ -1,5 +1,5 @@
 use crate::{
-    camera::{Camera, Observation},
+    camera::{Camera, IdMode, Observation},
     common::Coord,
     motion::Motion,
 };
@@ -43,35 +43,26 @@ pub struct Agent {
     pub time_interval: f64,
     pub pose: Pose,
     pub radius: f64, // ロボット半径
-    pub nu: f64,     // ロボットの前方方向の速度
-    pub omega: f64,  // ロボットの中心の角速度
     pub motion: Motion,
     pub camera: Camera,
     pub obs_records: Vec<Vec<Observation>>, //  ビジュアライザ用観測記録
     pub pose_records: Vec<Pose>,            //  ビジュアライザ用姿勢記録
+    pub control_records: Vec<(f64, f64)>,   //  各ステップに与えた制御指令(nu, omega)
 }
 
 impl Agent {
-    pub fn new(
-        id: u64,
-        time_interval: f64,
-        init_pose: Pose,
-        radius: f64,
-        nu: f64,
-        omega: f64,
-    ) -> Self {
+    pub fn new(id: u64, time_interval: f64, init_pose: Pose, radius: f64) -> Self {
         Agent {
             id,
             rng: Pcg64Mcg::seed_from_u64(id),
             time_interval,
             pose: init_pose,
             radius,
-            nu,
-            omega,
-            motion: Motion::new(),         // 理想の動き
-            camera: Camera::new(),         // 理想観測
-            obs_records: vec![vec![]],     // t=0では観測はしない
-            pose_records: vec![init_pose], // t=0は初期姿勢
+            motion: Motion::new(),             // 理想の動き
+            camera: Camera::new(),             // 理想観測
+            obs_records: vec![vec![]],         // t=0では観測はしない
+            pose_records: vec![init_pose],     // t=0は初期姿勢
+            control_records: vec![(0.0, 0.0)], // t=0では動かない
         }
     }
     pub fn set_motion_noise(&mut self, noise_per_meter: f64, noise_std: f64) {
@@ -90,6 +81,9 @@ impl Agent {
         self.motion
             .set_kidnap(&mut self.rng, expected_kidnap_time, width, height);
     }
+    pub fn set_camera_id_mode(&mut self, id_mode: IdMode) {
+        self.camera.set_id_mode(id_mode);
+    }
     pub fn set_camera_noise(&mut self, distance_noise_rate: f64, direction_noise: f64) {
         self.camera.set_noise(distance_noise_rate, direction_noise);
     }
@@ -106,16 +100,18 @@ impl Agent {
     pub fn set_camera_occlusion(&mut self, prob: f64) {
         self.camera.set_occlusion(prob);
     }
-    pub fn action(&mut self, landmarks: &[Coord]) -> Vec<Observation> {
+    // 制御指令nu(前方方向の速度), omega(中心の角速度)で1ステップ動いてから観測する
+    pub fn action(&mut self, nu: f64, omega: f64, landmarks: &[Coord]) -> Vec<Observation> {
         self.motion.state_transition_with_noise(
             &mut self.rng,
             self.time_interval,
             &mut self.pose,
             self.radius,
-            self.nu,
-            self.omega,
+            nu,
+            omega,
         );
         self.pose_records.push(self.pose);
+        self.control_records.push((nu, omega));
         let obs = self.camera.observe(&mut self.rng, self.pose, landmarks);
         self.obs_records.push(obs.clone());
         obs
@@ -0,0 +1,264 @@
+use crate::agent::Pose;
+use crate::camera::{observe_landmark, Observation};
+use crate::common::{convert_radian_in_range, Coord};
+use crate::ekf::jacobian_observation;
+use crate::matrix::Matrix;
+use crate::normal::chi_squared_quantile;
+
+// 観測とランドマークの対応付けの方法
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Association {
+    // 観測のidをそのまま使う
+    Known,
+    // 観測ごとに尤度が最大のランドマークに対応付ける
+    MaximumLikelihood,
+    // マハラノビス距離がゲート内で最小のランドマークに対応付ける
+    // ゲート内にランドマークがなければ, reject_outliersなら観測を捨て, そうでなければ最も近いものに対応付ける
+    Gated {
+        confidence: f64,
+        reject_outliers: bool,
+    },
+    // 同時に成り立つ対応付けのうち最も多くの観測を説明するものを分枝限定法で探す
+    // どのランドマークにも対応付けられなかった観測は捨てる
+    Jcbb {
+        confidence: f64,
+    },
+}
+
+// ランドマークを観測したときの予測値, 姿勢に関するヤコビアン, 観測雑音の共分散
+#[derive(Debug)]
+struct Prediction {
+    estimated: Observation,
+    h: Matrix, // 2x3
+    q: Matrix, // 2x2
+}
+
+// 推定姿勢(平均meanと共分散cov)から見て, 各観測がどのランドマークのものかを決める
+// 対応付けたランドマークの添字をidに入れた観測を返す(捨てた観測は含まない)
+pub fn associate(
+    association: Association,
+    mean: &Pose,
+    cov: &Matrix,
+    observation: &[Observation],
+    landmarks: &[Coord],
+    distance_rate_std: f64,
+    direction_std: f64,
+) -> Vec<Observation> {
+    if association == Association::Known || landmarks.is_empty() {
+        return observation.to_vec();
+    }
+    let predictions: Vec<Prediction> = landmarks
+        .iter()
+        .enumerate()
+        .map(|(id, mark)| {
+            let estimated = observe_landmark(mean, mark, id);
+            Prediction {
+                estimated,
+                h: jacobian_observation(mean, mark),
+                q: Matrix::diag(&[
+                    (distance_rate_std * estimated.dist).powf(2.0),
+                    direction_std.powf(2.0),
+                ]),
+            }
+        })
+        .collect();
+    // 観測iとランドマークjの組ごとのマハラノビス距離の2乗と負の対数尤度
+    let scores: Vec<Vec<(f64, f64)>> = observation
+        .iter()
+        .map(|obs| {
+            predictions
+                .iter()
+                .map(|p| {
+                    let s = &(&(&p.h * cov) * &p.h.transpose()) + &p.q;
+                    let v = innovation(obs, &p.estimated);
+                    match s.inverse() {
+                        Some(s_inv) => {
+                            let d2 = (&(&v.transpose() * &s_inv) * &v)[(0, 0)];
+                            (d2, 0.5 * d2 + 0.5 * s.determinant().ln())
+                        }
+                        None => (f64::INFINITY, f64::INFINITY),
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    let argmin = |values: Vec<f64>| {
+        (0..values.len())
+            .min_by(|&a, &b| values[a].total_cmp(&values[b]))
+            .unwrap()
+    };
+
+    let assignment: Vec<Option<usize>> = match association {
+        Association::Known => unreachable!(),
+        Association::MaximumLikelihood => scores
+            .iter()
+            .map(|row| Some(argmin(row.iter().map(|s| s.1).collect())))
+            .collect(),
+        Association::Gated {
+            confidence,
+            reject_outliers,
+        } => {
+            let gate = chi_squared_quantile(confidence, 2);
+            scores
+                .iter()
+                .map(|row| {
+                    let j = argmin(row.iter().map(|s| s.0).collect());
+                    if row[j].0 <= gate || !reject_outliers {
+                        Some(j)
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        }
+        Association::Jcbb { confidence } => {
+            let jcbb = Jcbb {
+                confidence,
+                mean_cov: cov,
+                observation,
+                predictions: &predictions,
+                scores: &scores,
+            };
+            let mut best = vec![None; observation.len()];
+            jcbb.search(0, &mut vec![], &mut best);
+            best
+        }
+    };
+    observation
+        .iter()
+        .zip(assignment.iter())
+        .filter_map(|(obs, j)| j.map(|id| Observation { id, ..*obs }))
+        .collect()
+}
+
+fn innovation(obs: &Observation, estimated: &Observation) -> Matrix {
+    Matrix::column(&[
+        obs.dist - estimated.dist,
+        convert_radian_in_range(obs.angle - estimated.angle),
+    ])
+}
+
+// Joint Compatibility Branch and Bound
+struct Jcbb<'a> {
+    confidence: f64,
+    mean_cov: &'a Matrix,
+    observation: &'a [Observation],
+    predictions: &'a [Prediction],
+    scores: &'a [Vec<(f64, f64)>],
+}
+
+impl Jcbb<'_> {
+    fn pairings(hypothesis: &[Option<usize>]) -> usize {
+        hypothesis.iter().filter(|j| j.is_some()).count()
+    }
+    // 対応付けた観測をまとめたときのマハラノビス距離がカイ二乗分布の閾値以内か
+    // 推定姿勢の誤差は全観測に共通なので, 観測間の相関も考慮する
+    fn jointly_compatible(&self, hypothesis: &[Option<usize>]) -> bool {
+        let pairs: Vec<(usize, usize)> = hypothesis
+            .iter()
+            .enumerate()
+            .filter_map(|(i, j)| j.map(|j| (i, j)))
+            .collect();
+        let k = pairs.len();
+        if k == 0 {
+            return true;
+        }
+        let mut h = Matrix::new(2 * k, 3);
+        let mut q = Matrix::new(2 * k, 2 * k);
+        let mut v = Matrix::new(2 * k, 1);
+        for (n, &(i, j)) in pairs.iter().enumerate() {
+            let p = &self.predictions[j];
+            h.set_block(2 * n, 0, &p.h);
+            q.set_block(2 * n, 2 * n, &p.q);
+            v.set_block(2 * n, 0, &innovation(&self.observation[i], &p.estimated));
+        }
+        let s = &(&(&h * self.mean_cov) * &h.transpose()) + &q;
+        match s.inverse() {
+            Some(s_inv) => {
+                (&(&v.transpose() * &s_inv) * &v)[(0, 0)]
+                    <= chi_squared_quantile(self.confidence, 2 * k)
+            }
+            None => false,
+        }
+    }
+    fn search(&self, i: usize, hypothesis: &mut Vec<Option<usize>>, best: &mut Vec<Option<usize>>) {
+        if i == self.observation.len() {
+            if Self::pairings(hypothesis) > Self::pairings(best) {
+                *best = hypothesis.clone();
+            }
+            return;
+        }
+        // 単独でゲートを通るランドマークをマハラノビス距離の小さい順に試す
+        let gate = chi_squared_quantile(self.confidence, 2);
+        let mut candidates: Vec<usize> = (0..self.predictions.len())
+            .filter(|&j| self.scores[i][j].0 <= gate)
+            .collect();
+        candidates.sort_by(|&a, &b| self.scores[i][a].0.total_cmp(&self.scores[i][b].0));
+        for j in candidates {
+            if hypothesis.contains(&Some(j)) {
+                continue;
+            }
+            hypothesis.push(Some(j));
+            if self.jointly_compatible(hypothesis) {
+                self.search(i + 1, hypothesis, best);
+            }
+            hypothesis.pop();
+        }
+        // 観測iを捨てても最良の対応付けを上回る見込みがあれば探す
+        let remaining = self.observation.len() - i - 1;
+        if Self::pairings(hypothesis) + remaining > Self::pairings(best) {
+            hypothesis.push(None);
+            self.search(i + 1, hypothesis, best);
+            hypothesis.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jcbb_pairs_nearby_landmarks_and_drops_clutter() {
+        // 0.4 m離れた2つのランドマークは, どちらの観測に対してもゲートを通る
+        let landmarks = [
+            Coord { x: 2.0, y: 0.2 },
+            Coord { x: 2.0, y: -0.2 },
+            Coord { x: -3.0, y: 2.0 },
+        ];
+        let truth = Pose {
+            coord: Coord { x: 0.0, y: 0.0 },
+            theta: 0.0,
+        };
+        let mean = Pose {
+            coord: Coord { x: 0.05, y: 0.05 },
+            theta: 0.02,
+        };
+        let cov = Matrix::diag(&[0.01, 0.01, 0.001]);
+        let strip = |obs: Observation| Observation {
+            id: Observation::UNKNOWN_ID,
+            ..obs
+        };
+        let observation = [
+            strip(observe_landmark(&truth, &landmarks[1], 1)),
+            strip(observe_landmark(&truth, &landmarks[0], 0)),
+            // どのランドマークとも合わない偽の観測
+            Observation {
+                id: Observation::UNKNOWN_ID,
+                dist: 1.0,
+                angle: 2.5,
+            },
+        ];
+        let associated = associate(
+            Association::Jcbb { confidence: 0.99 },
+            &mean,
+            &cov,
+            &observation,
+            &landmarks,
+            0.05,
+            0.05,
+        );
+        let ids: Vec<usize> = associated.iter().map(|obs| obs.id).collect();
+        assert_eq!(ids, vec![1, 0]);
+    }
+}
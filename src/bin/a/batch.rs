@@ -0,0 +1,269 @@
+use crate::metrics::{self, TrackingSummary};
+use crate::scenario::Scenario;
+use crate::simulate;
+
+// 1試行分の推定誤差の要約
+#[derive(Debug, Clone, Copy)]
+pub struct RunError {
+    pub position_rmse: f64,
+    pub heading_rmse: f64,
+    pub max_position_error: f64,
+    pub final_position_error: f64,
+    pub mean_particle_num: Option<f64>,
+    pub mean_ess: Option<f64>, // リサンプリング前の有効サンプルサイズの平均
+    pub mean_nees: Option<f64>,
+    pub bias: Option<BiasError>,
+}
+
+// バイアスを推定する推定器の, 真のバイアスに対する誤差(並びはBiasEstimateと同じ)
+#[derive(Debug, Clone, Copy)]
+pub struct BiasError {
+    pub errors: [[f64; 4]; BIAS_CHECKPOINTS.len()], // 各チェックポイントでの誤差
+    pub final_std: [f64; 4],                        // 最終ステップの推定の標準偏差
+}
+
+// 収束の様子を見るため, 走行時間に対するこれらの割合の時点でバイアスの誤差を記録する
+const BIAS_CHECKPOINTS: [f64; 3] = [0.25, 0.5, 1.0];
+const BIAS_NAMES: [&str; 4] = [
+    "nu factor",
+    "omega factor",
+    "distance rate",
+    "direction [deg]",
+];
+
+// 推定姿勢と真の姿勢(Agent::pose_records)の誤差を試行ごとに要約する
+// 経路を追従する制御なら真の軌跡の経路追従誤差も返す
+pub fn evaluate(
+    scenario: &Scenario,
+    seed_offset: u64,
+) -> (Vec<(String, RunError)>, Option<TrackingSummary>) {
+    let (input, output) = simulate(scenario, seed_offset);
+    let agent = &output.agents[0];
+    let truth = &agent.pose_records;
+    let true_bias = [
+        agent.motion.bias.nu_bias,
+        agent.motion.bias.omega_bias,
+        agent.camera.bias.distance_bias_rate,
+        agent.camera.bias.direction_bias,
+    ];
+    let max_turn = input.max_turn();
+    let mut ret = vec![];
+    for estimator in output.estimators.iter() {
+        let summary = metrics::evaluate(estimator.as_ref(), truth, max_turn).summary;
+        let n = max_turn.max(1) as f64;
+        let particle_nums: Option<Vec<usize>> = (1..=max_turn)
+            .map(|turn| estimator.particle_num(turn))
+            .collect();
+        let esss: Option<Vec<f64>> = (1..=max_turn)
+            .map(|turn| estimator.effective_sample_size(turn))
+            .collect();
+        let bias = estimator.bias_estimate(max_turn).map(|last| {
+            let mut errors = [[0.0; 4]; BIAS_CHECKPOINTS.len()];
+            for (errors, ratio) in errors.iter_mut().zip(BIAS_CHECKPOINTS.iter()) {
+                let turn = (ratio * max_turn as f64).round() as usize;
+                let estimate = estimator.bias_estimate(turn).unwrap();
+                for (i, e) in errors.iter_mut().enumerate() {
+                    *e = estimate.mean[i] - true_bias[i];
+                }
+            }
+            BiasError {
+                errors,
+                final_std: last.std,
+            }
+        });
+        ret.push((
+            estimator.name().to_string(),
+            RunError {
+                position_rmse: summary.position_rmse,
+                heading_rmse: summary.heading_rmse,
+                max_position_error: summary.max_position_error,
+                final_position_error: summary.final_position_error,
+                mean_particle_num: particle_nums.map(|nums| nums.iter().sum::<usize>() as f64 / n),
+                mean_ess: esss.map(|esss| esss.iter().sum::<f64>() / n),
+                mean_nees: summary.mean_nees,
+                bias,
+            },
+        ));
+    }
+    let tracking = scenario
+        .reference_path(&input)
+        .map(|path| metrics::evaluate_tracking(&path, truth, max_turn));
+    (ret, tracking)
+}
+
+// 昇順ソート済みの値から最近傍順位法でパーセンタイルを求める
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.max(1) - 1]
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+// runs個のシードで試行し推定器ごとの統計を出力する
+// 最終ステップの位置誤差がdivergence_thresholdを超えた試行を発散とみなす
+pub fn run(scenario: &Scenario, runs: usize, divergence_threshold: f64) {
+    let mut names: Vec<String> = vec![];
+    let mut errors: Vec<Vec<RunError>> = vec![];
+    let mut trackings: Vec<TrackingSummary> = vec![];
+    for seed_offset in 0..runs as u64 {
+        let (run_errors, tracking) = evaluate(scenario, seed_offset);
+        trackings.extend(tracking);
+        for (idx, (name, error)) in run_errors.into_iter().enumerate() {
+            if idx == names.len() {
+                names.push(name);
+                errors.push(vec![]);
+            }
+            errors[idx].push(error);
+        }
+    }
+
+    println!(
+        "runs: {}, steps: {}, divergence threshold: {} m",
+        runs,
+        scenario.input().max_turn(),
+        divergence_threshold
+    );
+    println!(
+        "{:<10} {:>22} {:>24} {:>10} {:>10} {:>10} {:>10} {:>10}",
+        "", "pos RMSE [m]", "heading RMSE [deg]", "max [m]", "diverged", "particles", "ESS", "NEES"
+    );
+    println!("{:<10} {:>22} {:>24}", "", "mean/p50/p95", "mean/p50/p95");
+    for (name, errors) in names.iter().zip(errors.iter()) {
+        if errors.is_empty() {
+            continue;
+        }
+        let mut position: Vec<f64> = errors.iter().map(|e| e.position_rmse).collect();
+        let mut heading: Vec<f64> = errors.iter().map(|e| e.heading_rmse.to_degrees()).collect();
+        position.sort_by(|a, b| a.total_cmp(b));
+        heading.sort_by(|a, b| a.total_cmp(b));
+        let max_error = errors
+            .iter()
+            .map(|e| e.max_position_error)
+            .fold(0.0, f64::max);
+        let particle_num = if errors.iter().all(|e| e.mean_particle_num.is_some()) {
+            let nums: Vec<f64> = errors.iter().filter_map(|e| e.mean_particle_num).collect();
+            format!("{:.1}", mean(&nums))
+        } else {
+            "-".to_string()
+        };
+        let ess = if errors.iter().all(|e| e.mean_ess.is_some()) {
+            let esss: Vec<f64> = errors.iter().filter_map(|e| e.mean_ess).collect();
+            format!("{:.1}", mean(&esss))
+        } else {
+            "-".to_string()
+        };
+        // NEESは姿勢の次元3に近いほど共分散が実際の誤差に見合っている
+        let neeses: Vec<f64> = errors.iter().filter_map(|e| e.mean_nees).collect();
+        let nees = if neeses.is_empty() {
+            "-".to_string()
+        } else {
+            format!("{:.2}", mean(&neeses))
+        };
+        let diverged = errors
+            .iter()
+            .filter(|e| e.final_position_error > divergence_threshold)
+            .count();
+        println!(
+            "{:<10} {:>8.3}/{:>6.3}/{:>6.3} {:>10.2}/{:>6.2}/{:>6.2} {:>10.3} {:>9.1}% {:>10} {:>10} {:>10}",
+            name,
+            mean(&position),
+            percentile(&position, 50.0),
+            percentile(&position, 95.0),
+            mean(&heading),
+            percentile(&heading, 50.0),
+            percentile(&heading, 95.0),
+            max_error,
+            100.0 * diverged as f64 / errors.len() as f64,
+            particle_num,
+            ess,
+            nees,
+        );
+    }
+    if !trackings.is_empty() {
+        let controller = scenario.control.is_closed_loop().then(|| names[0].as_str());
+        print_tracking(&trackings, controller);
+    }
+    for (name, errors) in names.iter().zip(errors.iter()) {
+        let biases: Vec<BiasError> = errors.iter().filter_map(|e| e.bias).collect();
+        if biases.is_empty() {
+            continue;
+        }
+        print_bias_convergence(name, &biases);
+    }
+}
+
+// 真の軌跡の経路からのずれを試行全体で要約する
+// 閉ループ制御ならcontrollerの推定誤差が経路追従誤差にどれだけ表れるかを比べられる
+fn print_tracking(trackings: &[TrackingSummary], controller: Option<&str>) {
+    let mut cross_track: Vec<f64> = trackings.iter().map(|t| t.cross_track_rmse).collect();
+    let mut goal: Vec<f64> = trackings.iter().map(|t| t.goal_error).collect();
+    cross_track.sort_by(|a, b| a.total_cmp(b));
+    goal.sort_by(|a, b| a.total_cmp(b));
+    let max_error = trackings
+        .iter()
+        .map(|t| t.max_cross_track_error)
+        .fold(0.0, f64::max);
+    println!();
+    println!(
+        "path tracking{}",
+        controller
+            .map(|name| format!(" (steered by {})", name))
+            .unwrap_or_default()
+    );
+    println!(
+        "{:<10} {:>22} {:>10} {:>22}",
+        "", "cross-track RMSE [m]", "max [m]", "goal error [m]"
+    );
+    println!(
+        "{:<10} {:>22} {:>10} {:>22}",
+        "", "mean/p50/p95", "", "mean/p50/p95"
+    );
+    println!(
+        "{:<10} {:>8.3}/{:>6.3}/{:>6.3} {:>10.3} {:>8.3}/{:>6.3}/{:>6.3}",
+        "truth",
+        mean(&cross_track),
+        percentile(&cross_track, 50.0),
+        percentile(&cross_track, 95.0),
+        max_error,
+        mean(&goal),
+        percentile(&goal, 50.0),
+        percentile(&goal, 95.0),
+    );
+}
+
+// バイアスの推定誤差のRMSが走行とともに小さくなるかを表示する
+fn print_bias_convergence(name: &str, biases: &[BiasError]) {
+    println!();
+    println!(
+        "{} bias estimation: RMS error at {} of the run, final std",
+        name,
+        BIAS_CHECKPOINTS
+            .iter()
+            .map(|r| format!("{:.0}%", r * 100.0))
+            .collect::<Vec<_>>()
+            .join("/")
+    );
+    for (i, bias_name) in BIAS_NAMES.iter().enumerate() {
+        // 方角は度で表示する
+        let unit = if i == 3 {
+            180.0 / std::f64::consts::PI
+        } else {
+            1.0
+        };
+        let rms: Vec<String> = (0..BIAS_CHECKPOINTS.len())
+            .map(|c| {
+                let squares: Vec<f64> = biases.iter().map(|b| b.errors[c][i].powf(2.0)).collect();
+                format!("{:.4}", mean(&squares).sqrt() * unit)
+            })
+            .collect();
+        let std: Vec<f64> = biases.iter().map(|b| b.final_std[i]).collect();
+        println!(
+            "{:<16} {:>24} {:>10.4}",
+            bias_name,
+            rms.join("/"),
+            mean(&std) * unit
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use crate::agent::Pose;
+use crate::association::{associate, Association};
+use crate::camera::{observe_landmark, Observation};
+use crate::common::{convert_radian_in_range, Coord};
+use crate::ekf::{jacobian_control, jacobian_observation, jacobian_state, motion_noise_cov};
+use crate::estimator::{Belief, BiasEstimate, Filter, MotionNoisePdf};
+use crate::matrix::Matrix;
+use crate::motion::state_transition;
+
+// 状態の次元: 姿勢(x, y, theta)とバイアス(nu, omega, distance_rate, direction)
+const DIM: usize = 7;
+
+// 動作と観測のバイアスも状態に加えて推定する拡張カルマンフィルタ
+// MotionBiasと同じく速度・角速度には係数が掛かり, ObservationBiasと同じく
+// 距離には距離に比例した誤差, 方角には一定の誤差が加わるものとする
+#[derive(Debug)]
+pub struct BiasEkf {
+    pub time_interval: f64,
+    pub radius: f64,
+    pub mean: Pose,
+    pub bias: [f64; 4], // 速度の係数, 角速度の係数, 距離の誤差率, 方角の誤差
+    pub cov: Matrix,    // 7x7
+    pub motion_noise_pdf: MotionNoisePdf,
+    pub distance_rate_std: f64,
+    pub direction_std: f64,
+    pub pose_records: Vec<Pose>,
+    pub cov_records: Vec<Matrix>,
+    pub bias_records: Vec<BiasEstimate>,
+    pub association: Association,
+}
+
+impl BiasEkf {
+    // bias_stdは各バイアスの事前分布の標準偏差(速度・角速度の係数は1, 観測の誤差は0を中心とする)
+    pub fn new(
+        time_interval: f64,
+        init_pose: Pose,
+        radius: f64,
+        motion_noise_pdf: MotionNoisePdf,
+        distance_rate_std: f64,
+        direction_std: f64,
+        bias_std: [f64; 4],
+    ) -> Self {
+        let bias = [1.0, 1.0, 0.0, 0.0];
+        let variances: Vec<f64> = [1e-10, 1e-10, 1e-10]
+            .iter()
+            .copied()
+            .chain(bias_std.iter().map(|std| std.powf(2.0)))
+            .collect();
+        let cov = Matrix::diag(&variances);
+        let mut ekf = Self {
+            time_interval,
+            radius,
+            mean: init_pose,
+            bias,
+            cov: cov.clone(),
+            motion_noise_pdf,
+            distance_rate_std,
+            direction_std,
+            pose_records: vec![init_pose],
+            cov_records: vec![cov.block(0, 0, 3, 3)],
+            bias_records: vec![],
+            association: Association::Known,
+        };
+        ekf.bias_records.push(ekf.current_bias());
+        ekf
+    }
+    pub fn set_association(&mut self, association: Association) {
+        self.association = association;
+    }
+    // 観測をランドマークに対応付ける(推定したバイアスを取り除いてから対応付け, 元の観測値に戻す)
+    pub fn associate(&self, observation: &[Observation], landmarks: &[Coord]) -> Vec<Observation> {
+        if self.association == Association::Known {
+            return observation.to_vec();
+        }
+        let (rate, direction) = (self.bias[2], self.bias[3]);
+        let corrected: Vec<Observation> = observation
+            .iter()
+            .map(|obs| Observation {
+                dist: obs.dist / (1.0 + rate),
+                angle: convert_radian_in_range(obs.angle - direction),
+                ..*obs
+            })
+            .collect();
+        associate(
+            self.association,
+            &self.mean,
+            &self.cov.block(0, 0, 3, 3),
+            &corrected,
+            landmarks,
+            self.distance_rate_std,
+            self.direction_std,
+        )
+        .into_iter()
+        .map(|obs| Observation {
+            dist: obs.dist * (1.0 + rate),
+            angle: convert_radian_in_range(obs.angle + direction),
+            ..obs
+        })
+        .collect()
+    }
+    pub fn update_motion(&mut self, prev_nu: f64, prev_omega: f64) {
+        let dt = self.time_interval;
+        // バイアスの係数を掛けた実際の制御指令で動かす
+        let nu = self.bias[0] * prev_nu;
+        let mut omega = self.bias[1] * prev_omega;
+        // ω=0ではヤコビアンが発散するため微小値に置き換える
+        if omega.abs() < 1e-5 {
+            omega = 1e-5;
+        }
+        let m = motion_noise_cov(&self.motion_noise_pdf, nu, omega, dt);
+        let a = jacobian_control(nu, omega, dt, self.mean.theta);
+        let mut f = Matrix::identity(DIM);
+        f.set_block(0, 0, &jacobian_state(nu, omega, dt, self.mean.theta));
+        // 係数に関するヤコビアンは制御指令に関するヤコビアンに元の指令を掛けたもの
+        f.set_block(0, 3, &a.block(0, 0, 3, 1).scale(prev_nu));
+        f.set_block(0, 4, &a.block(0, 1, 3, 1).scale(prev_omega));
+        let mut a_full = Matrix::new(DIM, 2);
+        a_full.set_block(0, 0, &a);
+        self.cov = (&(&(&f * &self.cov) * &f.transpose())
+            + &(&(&a_full * &m) * &a_full.transpose()))
+            .symmetrize();
+        self.mean = state_transition(dt, self.mean, nu, omega);
+    }
+    pub fn updater_observation(&mut self, observation: &[Observation], landmarks: &[Coord]) {
+        for obs in observation.iter() {
+            let mark = landmarks[obs.id];
+            let truth = observe_landmark(&self.mean, &mark, obs.id);
+            let (rate, direction) = (self.bias[2], self.bias[3]);
+            let estimated_dist = truth.dist * (1.0 + rate);
+            let estimated_angle = truth.angle + direction;
+            let h_r = jacobian_observation(&self.mean, &mark);
+            let mut h = Matrix::new(2, DIM);
+            h.set_block(0, 0, &h_r.block(0, 0, 1, 3).scale(1.0 + rate));
+            h.set_block(1, 0, &h_r.block(1, 0, 1, 3));
+            h[(0, 5)] = truth.dist;
+            h[(1, 6)] = 1.0;
+            let q = Matrix::diag(&[
+                (self.distance_rate_std * estimated_dist).powf(2.0),
+                self.direction_std.powf(2.0),
+            ]);
+            let s = &q + &(&(&h * &self.cov) * &h.transpose());
+            let s_inv = match s.inverse() {
+                Some(s_inv) => s_inv,
+                None => continue,
+            };
+            let k = &(&self.cov * &h.transpose()) * &s_inv;
+            let innovation = Matrix::column(&[
+                obs.dist - estimated_dist,
+                convert_radian_in_range(obs.angle - estimated_angle),
+            ]);
+            let delta = &k * &innovation;
+            self.mean.coord.x += delta[(0, 0)];
+            self.mean.coord.y += delta[(1, 0)];
+            self.mean.theta += delta[(2, 0)];
+            for (i, b) in self.bias.iter_mut().enumerate() {
+                *b += delta[(3 + i, 0)];
+            }
+            self.cov = (&(&Matrix::identity(DIM) - &(&k * &h)) * &self.cov).symmetrize();
+        }
+    }
+    fn current_bias(&self) -> BiasEstimate {
+        let mut std = [0.0; 4];
+        for (i, s) in std.iter_mut().enumerate() {
+            *s = self.cov[(3 + i, 3 + i)].sqrt();
+        }
+        BiasEstimate {
+            mean: self.bias,
+            std,
+        }
+    }
+    pub fn record(&mut self) {
+        self.pose_records.push(self.mean);
+        self.cov_records.push(self.cov.block(0, 0, 3, 3));
+        self.bias_records.push(self.current_bias());
+    }
+}
+
+impl Filter for BiasEkf {
+    fn name(&self) -> &str {
+        "EKF-bias"
+    }
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn predict(&mut self, nu: f64, omega: f64) {
+        self.update_motion(nu, omega);
+    }
+    fn update(&mut self, observation: &[Observation], landmarks: &[Coord]) {
+        let observation = self.associate(observation, landmarks);
+        self.updater_observation(&observation, landmarks);
+    }
+    fn finalize(&mut self) {
+        self.record();
+    }
+    fn pose(&self) -> Pose {
+        self.mean
+    }
+    fn estimate(&self, turn: usize) -> Pose {
+        self.pose_records[turn]
+    }
+    fn belief(&self, turn: usize) -> Belief {
+        Belief::Gaussian {
+            mean: self.pose_records[turn],
+            cov: self.cov_records[turn].clone(),
+        }
+    }
+    fn bias_estimate(&self, turn: usize) -> Option<BiasEstimate> {
+        Some(self.bias_records[turn])
+    }
+}
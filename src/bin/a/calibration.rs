@@ -0,0 +1,142 @@
+use std::f64::consts::PI;
+
+use crate::agent::Pose;
+use crate::camera::observe_landmark;
+use crate::common::{convert_radian_in_range, Coord};
+use crate::scenario::Scenario;
+
+// 直進の試行で走らせる道のり, m
+const STRAIGHT_DISTANCE: f64 = 4.0;
+// 旋回の試行で回る角度, rad
+const ROTATION_ANGLE: f64 = 4.0 * PI;
+// 観測の試行で置くランドマークの距離と方角
+const OBSERVATION_DISTANCES: [f64; 5] = [1.0, 2.0, 3.0, 4.0, 5.0];
+const OBSERVATION_DIRECTIONS: [f64; 3] = [-PI / 4.0, 0.0, PI / 4.0];
+// 観測の試行で1台あたりに観測する回数
+const OBSERVATION_NUM: usize = 10;
+// 推定値が0だと推定器の雑音が退化するので下限を設ける
+const STD_FLOOR: f64 = 1e-6;
+
+// 推定器に与える雑音の標準偏差(EstimatorConfigの同名の項目に対応)
+#[derive(Debug, Clone, Copy)]
+pub struct Calibration {
+    pub nn_std: f64,
+    pub no_std: f64,
+    pub on_std: f64,
+    pub oo_std: f64,
+    pub distance_rate_std: f64,
+    pub direction_std: f64,
+}
+
+// シナリオのエージェントと同じ雑音・バイアスを持つロボットをtrials台用意し,
+// 直進・その場旋回・静止しての観測の試行結果からMotionNoisePdfと観測の雑音を最尤推定する
+// バイアスはロボットごとに異なるので, 台数全体で見ると雑音の一部として推定される
+pub fn calibrate(scenario: &Scenario, trials: usize) -> Calibration {
+    let input = scenario.input();
+    let dt = input.time_interval;
+    let origin = Pose {
+        coord: Coord { x: 0.0, y: 0.0 },
+        theta: 0.0,
+    };
+
+    // 直進: 1ステップの速度の誤差はnn√(ν/Δt), 角速度の誤差はon√(ν/Δt)なので,
+    // 道のりLを走った後の距離と向きの誤差の分散はそれぞれnn^2 L, on^2 L
+    let nu = input.nu.abs();
+    let straight_steps = (STRAIGHT_DISTANCE / (nu * dt)).round() as usize;
+    let distance = nu * dt * straight_steps as f64;
+    let mut distance_errors = vec![];
+    let mut straight_theta_errors = vec![];
+    for i in 0..trials {
+        let mut agent = scenario.build_calibration_agent(&input, i as u64, origin);
+        for _ in 0..straight_steps {
+            agent.action(nu, 0.0, &[]);
+        }
+        distance_errors.push(agent.pose.coord.x - distance);
+        straight_theta_errors.push(convert_radian_in_range(agent.pose.theta));
+    }
+
+    // その場旋回: 1ステップの速度の誤差はno|ω|/Δt, 角速度の誤差はoo|ω|/Δtなので,
+    // Kステップ後の位置のずれの2乗と向きの誤差の分散はそれぞれK no^2 ω^2, K oo^2 ω^2
+    let omega = input.omega.abs();
+    let rotation_steps = (ROTATION_ANGLE / (omega * dt)).round() as usize;
+    let rotation = omega * dt * rotation_steps as f64;
+    let mut displacements = vec![];
+    let mut rotation_theta_errors = vec![];
+    for i in 0..trials {
+        let mut agent = scenario.build_calibration_agent(&input, i as u64, origin);
+        for _ in 0..rotation_steps {
+            agent.action(0.0, omega, &[]);
+        }
+        let c = agent.pose.coord;
+        displacements.push((c.x.powf(2.0) + c.y.powf(2.0)).sqrt());
+        rotation_theta_errors.push(convert_radian_in_range(agent.pose.theta - rotation));
+    }
+
+    // 観測: 既知の位置に置いたランドマークを静止したまま繰り返し観測する
+    let landmarks: Vec<Coord> = OBSERVATION_DISTANCES
+        .iter()
+        .flat_map(|&d| {
+            OBSERVATION_DIRECTIONS.iter().map(move |&a| Coord {
+                x: d * a.cos(),
+                y: d * a.sin(),
+            })
+        })
+        .collect();
+    let mut distance_rate_errors = vec![];
+    let mut direction_errors = vec![];
+    for i in 0..trials {
+        let mut agent = scenario.build_calibration_agent(&input, i as u64, origin);
+        for _ in 0..OBSERVATION_NUM {
+            for obs in agent.camera.observe(&mut agent.rng, origin, &landmarks) {
+                let truth = observe_landmark(&origin, &landmarks[obs.id], obs.id);
+                distance_rate_errors.push((obs.dist - truth.dist) / truth.dist);
+                direction_errors.push(convert_radian_in_range(obs.angle - truth.angle));
+            }
+        }
+    }
+
+    let k = |steps: usize| steps as f64 * omega.powf(2.0);
+    Calibration {
+        nn_std: (mean_square(&distance_errors) / distance)
+            .sqrt()
+            .max(STD_FLOOR),
+        no_std: (mean_square(&displacements) / k(rotation_steps))
+            .sqrt()
+            .max(STD_FLOOR),
+        on_std: (mean_square(&straight_theta_errors) / distance)
+            .sqrt()
+            .max(STD_FLOOR),
+        oo_std: (mean_square(&rotation_theta_errors) / k(rotation_steps))
+            .sqrt()
+            .max(STD_FLOOR),
+        distance_rate_std: mean_square(&distance_rate_errors).sqrt().max(STD_FLOOR),
+        direction_std: mean_square(&direction_errors).sqrt().max(STD_FLOOR),
+    }
+}
+
+// 誤差の平均が0の正規分布を仮定したときの分散の最尤推定値
+fn mean_square(errors: &[f64]) -> f64 {
+    if errors.is_empty() {
+        return 0.0;
+    }
+    errors.iter().map(|e| e * e).sum::<f64>() / errors.len() as f64
+}
+
+// 推定結果をシナリオファイルの[estimator]にそのまま貼れる形で出力する
+pub fn run(scenario: &Scenario, trials: usize) {
+    let c = calibrate(scenario, trials);
+    println!(
+        "# calibrated with {} robots: straight {} m, rotation {:.0} deg, {} observations each",
+        trials,
+        STRAIGHT_DISTANCE,
+        ROTATION_ANGLE.to_degrees(),
+        OBSERVATION_NUM * OBSERVATION_DISTANCES.len() * OBSERVATION_DIRECTIONS.len()
+    );
+    println!("[estimator]");
+    println!("nn_std = {:.6}", c.nn_std);
+    println!("no_std = {:.6}", c.no_std);
+    println!("on_std = {:.6}", c.on_std);
+    println!("oo_std = {:.6}", c.oo_std);
+    println!("distance_rate_std = {:.6}", c.distance_rate_std);
+    println!("direction_std = {:.6}", c.direction_std);
+}
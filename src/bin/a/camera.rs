@@ -14,6 +14,11 @@ pub struct Observation {
     pub angle: f64,
 }
 
+impl Observation {
+    // idを取り除いた観測に付けるid
+    pub const UNKNOWN_ID: usize = usize::MAX;
+}
+
 impl std::fmt::Display for Observation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "dist: {}, angle: {}", self.dist, self.angle)?;
@@ -21,8 +26,17 @@ impl std::fmt::Display for Observation {
     }
 }
 
+// 観測にランドマークのidをどう付けるか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdMode {
+    Known,     // 真のidを付ける
+    Stripped,  // idを付けない(Observation::UNKNOWN_ID)
+    Scrambled, // でたらめなidを付ける
+}
+
 #[derive(Debug)]
 pub struct Camera {
+    pub id_mode: IdMode,
     pub noise: ObservationNoise,
     pub bias: ObservationBias,
     pub phantom: Phantom,
@@ -31,11 +45,12 @@ pub struct Camera {
 }
 
 impl Camera {
-    const VIS_DISTANCE_RANGE: std::ops::Range<f64> = 0.5..6.0;
-    const VIS_DIRECTION_RANGE: std::ops::Range<f64> = -PI / 3.0..PI / 3.0;
+    pub const VIS_DISTANCE_RANGE: std::ops::Range<f64> = 0.5..6.0;
+    pub const VIS_DIRECTION_RANGE: std::ops::Range<f64> = -PI / 3.0..PI / 3.0;
     pub fn new() -> Self {
         let mut rng = Pcg64Mcg::seed_from_u64(0);
         Self {
+            id_mode: IdMode::Known,
             noise: ObservationNoise::new(0.0, 0.0),
             bias: ObservationBias::new(&mut rng, 0.0, 0.0),
             phantom: Phantom::new(0.0, 0.0, 0.0),
@@ -46,6 +61,9 @@ impl Camera {
     pub fn is_visible(&self, dist: &f64, angle: &f64) -> bool {
         Self::VIS_DISTANCE_RANGE.contains(dist) && Self::VIS_DIRECTION_RANGE.contains(angle)
     }
+    pub fn set_id_mode(&mut self, id_mode: IdMode) {
+        self.id_mode = id_mode;
+    }
     pub fn set_noise(&mut self, distance_noise_rate: f64, direction_noise: f64) {
         self.noise = ObservationNoise::new(distance_noise_rate, direction_noise)
     }
@@ -87,7 +105,12 @@ impl Camera {
                 self.bias.on(&mut dist, &mut angle);
                 self.noise.occur(rng, &mut dist, &mut angle);
                 angle = convert_radian_in_range(angle);
-                obs.push(Observation {id, dist, angle })
+                let id = match self.id_mode {
+                    IdMode::Known => id,
+                    IdMode::Stripped => Observation::UNKNOWN_ID,
+                    IdMode::Scrambled => rng.gen_range(0..landmarks.len()),
+                };
+                obs.push(Observation { id, dist, angle })
             }
         }
         obs
@@ -0,0 +1,282 @@
+use crate::agent::Pose;
+use crate::common::{convert_radian_in_range, Coord};
+use std::f64::consts::FRAC_PI_2;
+
+// 各ステップでエージェントと推定器に同じく与える制御指令を決める
+pub trait ControlPolicy {
+    // turn-1ステップ目からturnステップ目までの間に与える(nu, omega)
+    // estimateはturn-1ステップ目での推定姿勢(予定表どおりに走る方策は使わない)
+    fn control(&mut self, turn: usize, estimate: &Pose) -> (f64, f64);
+}
+
+// 常に同じ制御指令を与える(円弧を描く)
+#[derive(Debug, Clone, Copy)]
+pub struct ConstantControl {
+    pub nu: f64,
+    pub omega: f64,
+}
+
+impl ControlPolicy for ConstantControl {
+    fn control(&mut self, _turn: usize, _estimate: &Pose) -> (f64, f64) {
+        (self.nu, self.omega)
+    }
+}
+
+// durationの間, 一定の制御指令を与える区間
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Segment {
+    pub duration: f64, // sec
+    pub nu: f64,
+    pub omega: f64,
+}
+
+// 区間を順にたどる制御指令の予定表. 最後の区間を過ぎたら止まる
+// 区間の境目はステップの中央の時刻で判定するので, 区間の長さはtime_intervalの倍数に丸められる
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub time_interval: f64,
+    pub segments: Vec<Segment>,
+}
+
+impl Schedule {
+    pub fn new(time_interval: f64, segments: Vec<Segment>) -> Self {
+        Self {
+            time_interval,
+            segments,
+        }
+    }
+    // init_poseからwaypointsへ, その場旋回と直進を繰り返して順に向かう予定表を作る
+    // 推定姿勢は見ないので, 動作の雑音があればずれたまま進む
+    pub fn from_waypoints(
+        time_interval: f64,
+        init_pose: Pose,
+        waypoints: &[Coord],
+        nu: f64,
+        omega: f64,
+    ) -> Self {
+        let (nu, omega) = (nu.abs(), omega.abs());
+        let mut segments = vec![];
+        let mut pose = init_pose;
+        for &goal in waypoints.iter() {
+            let (dx, dy) = (goal.x - pose.coord.x, goal.y - pose.coord.y);
+            let heading = dy.atan2(dx);
+            let turn = convert_radian_in_range(heading - pose.theta);
+            if turn != 0.0 {
+                segments.push(Segment {
+                    duration: turn.abs() / omega,
+                    nu: 0.0,
+                    omega: omega.copysign(turn),
+                });
+            }
+            segments.push(Segment {
+                duration: (dx * dx + dy * dy).sqrt() / nu,
+                nu,
+                omega: 0.0,
+            });
+            pose = Pose {
+                coord: goal,
+                theta: heading,
+            };
+        }
+        Self::new(time_interval, segments)
+    }
+    pub fn duration(&self) -> f64 {
+        self.segments.iter().map(|s| s.duration).sum()
+    }
+}
+
+impl ControlPolicy for Schedule {
+    fn control(&mut self, turn: usize, _estimate: &Pose) -> (f64, f64) {
+        let time = (turn as f64 - 0.5) * self.time_interval;
+        let mut end = 0.0;
+        for segment in self.segments.iter() {
+            end += segment.duration;
+            if time < end {
+                return (segment.nu, segment.omega);
+            }
+        }
+        (0.0, 0.0)
+    }
+}
+
+// 推定姿勢から, 初期位置とwaypointsを結ぶ折れ線の経路をたどるPure Pursuit制御
+// 経路上で推定位置に最も近い点からlookaheadだけ先の点へ向かう円弧の曲率で旋回する
+// 最後のwaypointまでgoal_toleranceより近づいたら止まる
+#[derive(Debug, Clone)]
+pub struct PurePursuit {
+    path: Vec<Coord>,
+    nu: f64,
+    max_omega: f64,
+    lookahead: f64,
+    goal_tolerance: f64,
+    segment: usize, // 推定位置を射影している経路の区間(後戻りしない)
+    reached: bool,
+}
+
+impl PurePursuit {
+    pub fn new(
+        init_pose: Pose,
+        waypoints: &[Coord],
+        nu: f64,
+        max_omega: f64,
+        lookahead: f64,
+        goal_tolerance: f64,
+    ) -> Self {
+        let mut path = vec![init_pose.coord];
+        path.extend_from_slice(waypoints);
+        Self {
+            path,
+            nu: nu.abs(),
+            max_omega: max_omega.abs(),
+            lookahead,
+            goal_tolerance,
+            segment: 0,
+            reached: false,
+        }
+    }
+    // 経路を始点からの道のりで表したときの, 点pに最も近い点の道のり
+    // 後戻りしないよう, 今の区間より先だけを探す
+    fn project(&mut self, p: &Coord) -> f64 {
+        let mut start = 0.0;
+        let mut best = (f64::INFINITY, 0, 0.0);
+        for i in 0..self.path.len() - 1 {
+            let (a, b) = (self.path[i], self.path[i + 1]);
+            let length = distance(&a, &b);
+            if i >= self.segment {
+                let (d, t) = closest_on_segment(&a, &b, p);
+                if d < best.0 {
+                    best = (d, i, start + t * length);
+                }
+            }
+            start += length;
+        }
+        self.segment = best.1;
+        best.2
+    }
+    // 始点からの道のりsにある経路上の点(経路の長さを超えたら終点)
+    fn point_at(&self, mut s: f64) -> Coord {
+        for w in self.path.windows(2) {
+            let length = distance(&w[0], &w[1]);
+            if s <= length && length > 0.0 {
+                let t = s / length;
+                return Coord {
+                    x: w[0].x + t * (w[1].x - w[0].x),
+                    y: w[0].y + t * (w[1].y - w[0].y),
+                };
+            }
+            s -= length;
+        }
+        *self.path.last().unwrap()
+    }
+}
+
+impl ControlPolicy for PurePursuit {
+    fn control(&mut self, _turn: usize, estimate: &Pose) -> (f64, f64) {
+        let goal = *self.path.last().unwrap();
+        if self.reached || distance(&estimate.coord, &goal) < self.goal_tolerance {
+            self.reached = true;
+            return (0.0, 0.0);
+        }
+        let s = self.project(&estimate.coord);
+        let target = self.point_at(s + self.lookahead);
+        let (dx, dy) = (target.x - estimate.coord.x, target.y - estimate.coord.y);
+        let alpha = convert_radian_in_range(dy.atan2(dx) - estimate.theta);
+        // 注視点が後ろにあればその場で向きを変える
+        if alpha.abs() > FRAC_PI_2 {
+            return (0.0, self.max_omega.copysign(alpha));
+        }
+        let curvature = 2.0 * alpha.sin() / (dx * dx + dy * dy).sqrt();
+        let omega = (self.nu * curvature).clamp(-self.max_omega, self.max_omega);
+        (self.nu, omega)
+    }
+}
+
+// 推定姿勢から目標姿勢へ順に向かう極座標の姿勢制御
+// rhoは目標までの距離, alphaは目標の方向と向きの差, betaは目標の向きと目標の方向の差で,
+// k_rho > 0, k_beta < 0, k_alpha > k_rhoなら目標姿勢に収束する
+// 目標姿勢までgoal_toleranceより近づいたら次の目標に移り, 最後の目標で止まる
+#[derive(Debug, Clone)]
+pub struct GoToPose {
+    poses: Vec<Pose>,
+    max_nu: f64,
+    max_omega: f64,
+    k_rho: f64,
+    k_alpha: f64,
+    k_beta: f64,
+    goal_tolerance: f64,
+    index: usize, // 今向かっている目標姿勢
+}
+
+impl GoToPose {
+    pub fn new(
+        poses: Vec<Pose>,
+        max_nu: f64,
+        max_omega: f64,
+        k_rho: f64,
+        k_alpha: f64,
+        k_beta: f64,
+        goal_tolerance: f64,
+    ) -> Self {
+        Self {
+            poses,
+            max_nu: max_nu.abs(),
+            max_omega: max_omega.abs(),
+            k_rho,
+            k_alpha,
+            k_beta,
+            goal_tolerance,
+            index: 0,
+        }
+    }
+}
+
+impl ControlPolicy for GoToPose {
+    fn control(&mut self, _turn: usize, estimate: &Pose) -> (f64, f64) {
+        while self.index < self.poses.len()
+            && distance(&estimate.coord, &self.poses[self.index].coord) < self.goal_tolerance
+        {
+            self.index += 1;
+        }
+        let goal = match self.poses.get(self.index) {
+            Some(goal) => goal,
+            None => return (0.0, 0.0),
+        };
+        let (dx, dy) = (
+            goal.coord.x - estimate.coord.x,
+            goal.coord.y - estimate.coord.y,
+        );
+        let rho = (dx * dx + dy * dy).sqrt();
+        let direction = dy.atan2(dx);
+        let alpha = convert_radian_in_range(direction - estimate.theta);
+        let beta = convert_radian_in_range(goal.theta - direction);
+        let omega =
+            (self.k_alpha * alpha + self.k_beta * beta).clamp(-self.max_omega, self.max_omega);
+        // 目標が後ろにあればその場で向きを変える
+        let nu = if alpha.abs() > FRAC_PI_2 {
+            0.0
+        } else {
+            (self.k_rho * rho).min(self.max_nu)
+        };
+        (nu, omega)
+    }
+}
+
+fn distance(a: &Coord, b: &Coord) -> f64 {
+    ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+}
+
+// 線分abで点pに最も近い点までの距離と, その点の線分上の位置(0でa, 1でb)
+pub fn closest_on_segment(a: &Coord, b: &Coord, p: &Coord) -> (f64, f64) {
+    let (vx, vy) = (b.x - a.x, b.y - a.y);
+    let length2 = vx * vx + vy * vy;
+    let t = if length2 > 0.0 {
+        (((p.x - a.x) * vx + (p.y - a.y) * vy) / length2).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let closest = Coord {
+        x: a.x + t * vx,
+        y: a.y + t * vy,
+    };
+    (distance(&closest, p), t)
+}
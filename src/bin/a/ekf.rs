@@ -0,0 +1,184 @@
+use crate::agent::Pose;
+use crate::association::{associate, Association};
+use crate::camera::{observe_landmark, Observation};
+use crate::common::{convert_radian_in_range, Coord};
+use crate::estimator::{Belief, Filter, MotionNoisePdf};
+use crate::matrix::Matrix;
+use crate::motion::state_transition;
+
+// 拡張カルマンフィルタによる自己位置推定
+#[derive(Debug)]
+pub struct EkfEstimator {
+    pub time_interval: f64,
+    pub radius: f64,
+    pub mean: Pose,
+    pub cov: Matrix, // 3x3 (x, y, theta)
+    pub motion_noise_pdf: MotionNoisePdf,
+    pub distance_rate_std: f64,
+    pub direction_std: f64,
+    pub pose_records: Vec<Pose>,
+    pub cov_records: Vec<Matrix>,
+    pub association: Association,
+}
+
+impl EkfEstimator {
+    pub fn new(
+        time_interval: f64,
+        init_pose: Pose,
+        radius: f64,
+        motion_noise_pdf: MotionNoisePdf,
+        distance_rate_std: f64,
+        direction_std: f64,
+    ) -> Self {
+        let cov = Matrix::diag(&[1e-10, 1e-10, 1e-10]);
+        Self {
+            time_interval,
+            radius,
+            mean: init_pose,
+            cov: cov.clone(),
+            motion_noise_pdf,
+            distance_rate_std,
+            direction_std,
+            pose_records: vec![init_pose],
+            cov_records: vec![cov],
+            association: Association::Known,
+        }
+    }
+    pub fn set_association(&mut self, association: Association) {
+        self.association = association;
+    }
+    // 観測をランドマークに対応付ける(動作更新後の推定姿勢を使う)
+    pub fn associate(&self, observation: &[Observation], landmarks: &[Coord]) -> Vec<Observation> {
+        associate(
+            self.association,
+            &self.mean,
+            &self.cov,
+            observation,
+            landmarks,
+            self.distance_rate_std,
+            self.direction_std,
+        )
+    }
+    pub fn update_motion(&mut self, prev_nu: f64, mut prev_omega: f64) {
+        // ω=0ではヤコビアンが発散するため微小値に置き換える
+        if prev_omega.abs() < 1e-5 {
+            prev_omega = 1e-5;
+        }
+        let dt = self.time_interval;
+        let m = motion_noise_cov(&self.motion_noise_pdf, prev_nu, prev_omega, dt);
+        let a = jacobian_control(prev_nu, prev_omega, dt, self.mean.theta);
+        let f = jacobian_state(prev_nu, prev_omega, dt, self.mean.theta);
+        self.cov =
+            (&(&(&f * &self.cov) * &f.transpose()) + &(&(&a * &m) * &a.transpose())).symmetrize();
+        self.mean = state_transition(dt, self.mean, prev_nu, prev_omega);
+    }
+    pub fn updater_observation(&mut self, observation: &[Observation], landmarks: &[Coord]) {
+        for obs in observation.iter() {
+            let mark = landmarks[obs.id];
+            let estimated = observe_landmark(&self.mean, &mark, obs.id);
+            let h = jacobian_observation(&self.mean, &mark);
+            let q = Matrix::diag(&[
+                (self.distance_rate_std * estimated.dist).powf(2.0),
+                self.direction_std.powf(2.0),
+            ]);
+            let s = &q + &(&(&h * &self.cov) * &h.transpose());
+            let s_inv = match s.inverse() {
+                Some(s_inv) => s_inv,
+                None => continue,
+            };
+            let k = &(&self.cov * &h.transpose()) * &s_inv;
+            let innovation = Matrix::column(&[
+                obs.dist - estimated.dist,
+                convert_radian_in_range(obs.angle - estimated.angle),
+            ]);
+            let delta = &k * &innovation;
+            self.mean.coord.x += delta[(0, 0)];
+            self.mean.coord.y += delta[(1, 0)];
+            self.mean.theta += delta[(2, 0)];
+            self.cov = (&(&Matrix::identity(3) - &(&k * &h)) * &self.cov).symmetrize();
+        }
+    }
+    pub fn record(&mut self) {
+        self.pose_records.push(self.mean);
+        self.cov_records.push(self.cov.clone());
+    }
+}
+
+impl Filter for EkfEstimator {
+    fn name(&self) -> &str {
+        "EKF"
+    }
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn predict(&mut self, nu: f64, omega: f64) {
+        self.update_motion(nu, omega);
+    }
+    fn update(&mut self, observation: &[Observation], landmarks: &[Coord]) {
+        let observation = self.associate(observation, landmarks);
+        self.updater_observation(&observation, landmarks);
+    }
+    fn finalize(&mut self) {
+        self.record();
+    }
+    fn pose(&self) -> Pose {
+        self.mean
+    }
+    fn estimate(&self, turn: usize) -> Pose {
+        self.pose_records[turn]
+    }
+    fn belief(&self, turn: usize) -> Belief {
+        Belief::Gaussian {
+            mean: self.pose_records[turn],
+            cov: self.cov_records[turn].clone(),
+        }
+    }
+}
+
+// 制御指令(ν, ω)の空間での雑音の共分散行列M(MotionNoisePdf::noised_controlと同じ分散)
+pub fn motion_noise_cov(pdf: &MotionNoisePdf, nu: f64, omega: f64, dt: f64) -> Matrix {
+    Matrix::diag(&[
+        pdf.nn_pdf.std().powf(2.0) * nu.abs() / dt
+            + pdf.no_pdf.std().powf(2.0) * (omega / dt).powf(2.0),
+        pdf.on_pdf.std().powf(2.0) * nu.abs() / dt
+            + pdf.oo_pdf.std().powf(2.0) * (omega / dt).powf(2.0),
+    ])
+}
+
+// 状態遷移関数の制御指令(ν, ω)に関するヤコビアンA
+pub fn jacobian_control(nu: f64, omega: f64, dt: f64, theta: f64) -> Matrix {
+    let (st, ct) = (theta.sin(), theta.cos());
+    let (stw, ctw) = ((theta + omega * dt).sin(), (theta + omega * dt).cos());
+    Matrix::from_vec(
+        3,
+        2,
+        vec![
+            (stw - st) / omega,
+            -nu / omega.powf(2.0) * (stw - st) + nu / omega * dt * ctw,
+            (-ctw + ct) / omega,
+            -nu / omega.powf(2.0) * (-ctw + ct) + nu / omega * dt * stw,
+            0.0,
+            dt,
+        ],
+    )
+}
+
+// 状態遷移関数の姿勢に関するヤコビアンF
+pub fn jacobian_state(nu: f64, omega: f64, dt: f64, theta: f64) -> Matrix {
+    let mut f = Matrix::identity(3);
+    f[(0, 2)] = nu / omega * ((theta + omega * dt).cos() - theta.cos());
+    f[(1, 2)] = nu / omega * ((theta + omega * dt).sin() - theta.sin());
+    f
+}
+
+// 観測関数の姿勢に関するヤコビアンH
+pub fn jacobian_observation(pose: &Pose, mark: &Coord) -> Matrix {
+    let dx = pose.coord.x - mark.x;
+    let dy = pose.coord.y - mark.y;
+    let q = dx.powf(2.0) + dy.powf(2.0);
+    Matrix::from_vec(
+        2,
+        3,
+        vec![dx / q.sqrt(), dy / q.sqrt(), 0.0, -dy / q, dx / q, -1.0],
+    )
+}
@@ -0,0 +1,198 @@
+use crate::agent::Pose;
+use crate::camera::{observe_landmark, Observation};
+use crate::common::{convert_radian_in_range, Coord};
+use crate::ekf::{jacobian_control, jacobian_observation, jacobian_state, motion_noise_cov};
+use crate::estimator::{Belief, Filter, LandmarkEstimate, MotionNoisePdf};
+use crate::matrix::Matrix;
+use crate::motion::state_transition;
+
+// EKF-SLAM
+// 状態は(x, y, theta, m1x, m1y, m2x, m2y, ...)で, ランドマークは初めて観測したときに追加する
+#[derive(Debug)]
+pub struct EkfSlam {
+    pub time_interval: f64,
+    pub radius: f64,
+    pub pose: Pose,
+    pub marks: Vec<Coord>,
+    pub mark_ids: Vec<usize>, // marks[i]に対応する観測のid
+    pub cov: Matrix,          // (3 + 2n)x(3 + 2n)
+    pub motion_noise_pdf: MotionNoisePdf,
+    pub distance_rate_std: f64,
+    pub direction_std: f64,
+    pub pose_records: Vec<Pose>,
+    pub cov_records: Vec<Matrix>,
+    pub map_records: Vec<Vec<LandmarkEstimate>>,
+}
+
+impl EkfSlam {
+    pub fn new(
+        time_interval: f64,
+        init_pose: Pose,
+        radius: f64,
+        motion_noise_pdf: MotionNoisePdf,
+        distance_rate_std: f64,
+        direction_std: f64,
+    ) -> Self {
+        let cov = Matrix::diag(&[1e-10, 1e-10, 1e-10]);
+        Self {
+            time_interval,
+            radius,
+            pose: init_pose,
+            marks: vec![],
+            mark_ids: vec![],
+            cov: cov.clone(),
+            motion_noise_pdf,
+            distance_rate_std,
+            direction_std,
+            pose_records: vec![init_pose],
+            cov_records: vec![cov],
+            map_records: vec![vec![]],
+        }
+    }
+    fn dim(&self) -> usize {
+        3 + 2 * self.marks.len()
+    }
+    pub fn update_motion(&mut self, prev_nu: f64, mut prev_omega: f64) {
+        // ω=0ではヤコビアンが発散するため微小値に置き換える
+        if prev_omega.abs() < 1e-5 {
+            prev_omega = 1e-5;
+        }
+        let dt = self.time_interval;
+        let n = self.dim();
+        let m = motion_noise_cov(&self.motion_noise_pdf, prev_nu, prev_omega, dt);
+        let a = jacobian_control(prev_nu, prev_omega, dt, self.pose.theta);
+        let f = jacobian_state(prev_nu, prev_omega, dt, self.pose.theta);
+        // ランドマークは動かないので, 姿勢のブロックと姿勢・ランドマーク間のブロックだけ更新する
+        let p_rr = self.cov.block(0, 0, 3, 3);
+        let p_rr = &(&(&f * &p_rr) * &f.transpose()) + &(&(&a * &m) * &a.transpose());
+        self.cov.set_block(0, 0, &p_rr);
+        if n > 3 {
+            let p_rm = &f * &self.cov.block(0, 3, 3, n - 3);
+            self.cov.set_block(0, 3, &p_rm);
+            self.cov.set_block(3, 0, &p_rm.transpose());
+        }
+        self.cov = self.cov.symmetrize();
+        self.pose = state_transition(dt, self.pose, prev_nu, prev_omega);
+    }
+    // 観測雑音の共分散行列Q
+    fn observation_noise_cov(&self, dist: f64) -> Matrix {
+        Matrix::diag(&[
+            (self.distance_rate_std * dist).powf(2.0),
+            self.direction_std.powf(2.0),
+        ])
+    }
+    // 初めて観測したランドマークを観測値から地図に追加する
+    fn add_landmark(&mut self, obs: &Observation) {
+        let n = self.dim();
+        let phi = self.pose.theta + obs.angle;
+        let (s, c) = (phi.sin(), phi.cos());
+        self.marks.push(Coord {
+            x: self.pose.coord.x + obs.dist * c,
+            y: self.pose.coord.y + obs.dist * s,
+        });
+        self.mark_ids.push(obs.id);
+        // ランドマーク位置の姿勢と観測値に関するヤコビアン
+        let g_r = Matrix::from_vec(2, 3, vec![1.0, 0.0, -obs.dist * s, 0.0, 1.0, obs.dist * c]);
+        let g_z = Matrix::from_vec(2, 2, vec![c, -obs.dist * s, s, obs.dist * c]);
+        let q = self.observation_noise_cov(obs.dist);
+        let p_rr = self.cov.block(0, 0, 3, 3);
+        let p_mm = &(&(&g_r * &p_rr) * &g_r.transpose()) + &(&(&g_z * &q) * &g_z.transpose());
+        let p_mx = &g_r * &self.cov.block(0, 0, 3, n);
+        let mut cov = Matrix::new(n + 2, n + 2);
+        cov.set_block(0, 0, &self.cov);
+        cov.set_block(n, 0, &p_mx);
+        cov.set_block(0, n, &p_mx.transpose());
+        cov.set_block(n, n, &p_mm);
+        self.cov = cov.symmetrize();
+    }
+    pub fn updater_observation(&mut self, observation: &[Observation]) {
+        for obs in observation.iter() {
+            let idx = match self.mark_ids.iter().position(|&id| id == obs.id) {
+                Some(idx) => idx,
+                None => {
+                    self.add_landmark(obs);
+                    continue;
+                }
+            };
+            let n = self.dim();
+            let mark = self.marks[idx];
+            let estimated = observe_landmark(&self.pose, &mark, obs.id);
+            // 観測関数は姿勢とidx番目のランドマークにだけ依存する
+            let h_r = jacobian_observation(&self.pose, &mark);
+            let mut h = Matrix::new(2, n);
+            h.set_block(0, 0, &h_r);
+            h.set_block(0, 3 + 2 * idx, &h_r.block(0, 0, 2, 2).scale(-1.0));
+            let q = self.observation_noise_cov(estimated.dist);
+            let s = &q + &(&(&h * &self.cov) * &h.transpose());
+            let s_inv = match s.inverse() {
+                Some(s_inv) => s_inv,
+                None => continue,
+            };
+            let k = &(&self.cov * &h.transpose()) * &s_inv;
+            let innovation = Matrix::column(&[
+                obs.dist - estimated.dist,
+                convert_radian_in_range(obs.angle - estimated.angle),
+            ]);
+            let delta = &k * &innovation;
+            self.pose.coord.x += delta[(0, 0)];
+            self.pose.coord.y += delta[(1, 0)];
+            self.pose.theta += delta[(2, 0)];
+            for (i, mark) in self.marks.iter_mut().enumerate() {
+                mark.x += delta[(3 + 2 * i, 0)];
+                mark.y += delta[(4 + 2 * i, 0)];
+            }
+            self.cov = (&(&Matrix::identity(n) - &(&k * &h)) * &self.cov).symmetrize();
+        }
+    }
+    pub fn map(&self) -> Vec<LandmarkEstimate> {
+        self.marks
+            .iter()
+            .zip(self.mark_ids.iter())
+            .enumerate()
+            .map(|(i, (&coord, &id))| LandmarkEstimate {
+                id,
+                coord,
+                cov: self.cov.block(3 + 2 * i, 3 + 2 * i, 2, 2),
+            })
+            .collect()
+    }
+    pub fn record(&mut self) {
+        self.pose_records.push(self.pose);
+        self.cov_records.push(self.cov.block(0, 0, 3, 3));
+        self.map_records.push(self.map());
+    }
+}
+
+impl Filter for EkfSlam {
+    fn name(&self) -> &str {
+        "EKF-SLAM"
+    }
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn predict(&mut self, nu: f64, omega: f64) {
+        self.update_motion(nu, omega);
+    }
+    // 地図は推定するので真のランドマーク位置は使わない
+    fn update(&mut self, observation: &[Observation], _landmarks: &[Coord]) {
+        self.updater_observation(observation);
+    }
+    fn finalize(&mut self) {
+        self.record();
+    }
+    fn pose(&self) -> Pose {
+        self.pose
+    }
+    fn estimate(&self, turn: usize) -> Pose {
+        self.pose_records[turn]
+    }
+    fn belief(&self, turn: usize) -> Belief {
+        Belief::Gaussian {
+            mean: self.pose_records[turn],
+            cov: self.cov_records[turn].clone(),
+        }
+    }
+    fn landmark_estimates(&self, turn: usize) -> Vec<LandmarkEstimate> {
+        self.map_records[turn].clone()
+    }
+}
@@ -1,25 +1,210 @@
 use rand::{Rng, SeedableRng};
 use rand_pcg::Pcg64Mcg;
+use std::collections::HashSet;
 
 use crate::agent::Pose;
+use crate::association::{associate, Association};
 use crate::camera::{observe_landmark, Observation};
-use crate::common::Coord;
+use crate::common::{convert_radian_in_range, Coord};
+use crate::likelihood::ObservationModel;
+use crate::matrix::Matrix;
 use crate::motion::state_transition;
-use crate::normal::Normal;
+use crate::normal::{standard_normal_quantile, Normal};
+use crate::reset::{Reset, ResetEvent, ResetStrategy};
+
+// ビジュアライザ描画用の信念のスナップショット
+#[derive(Debug, Clone)]
+pub enum Belief {
+    Particles {
+        poses: Vec<Pose>,
+        mean: Pose,
+        cov: Matrix,
+    },
+    Gaussian {
+        mean: Pose,
+        cov: Matrix,
+    },
+}
+
+// SLAMで推定したランドマーク
+#[derive(Debug, Clone)]
+pub struct LandmarkEstimate {
+    pub id: usize,
+    pub coord: Coord,
+    pub cov: Matrix, // 2x2 (x, y)
+}
+
+// 推定した動作・観測のバイアス
+// 並びは速度の係数, 角速度の係数(MotionBias), 距離の誤差率, 方角の誤差(ObservationBias)
+#[derive(Debug, Clone, Copy)]
+pub struct BiasEstimate {
+    pub mean: [f64; 4],
+    pub std: [f64; 4],
+}
+
+// 各推定器が実装する共通インターフェース
+pub trait Filter {
+    fn name(&self) -> &str;
+    fn radius(&self) -> f64;
+    // 制御指令による動作更新
+    fn predict(&mut self, nu: f64, omega: f64);
+    // 観測による信念の更新
+    fn update(&mut self, observation: &[Observation], landmarks: &[Coord]);
+    // リサンプリングや記録など1ステップの締めくくり
+    fn finalize(&mut self);
+    // 現在の推定姿勢
+    fn pose(&self) -> Pose;
+    // turnステップ目の推定姿勢
+    fn estimate(&self, turn: usize) -> Pose;
+    // turnステップ目の信念
+    fn belief(&self, turn: usize) -> Belief;
+    // turnステップ目のパーティクル数(パーティクルを使わない推定器はNone)
+    fn particle_num(&self, _turn: usize) -> Option<usize> {
+        None
+    }
+    // turnステップ目のリサンプリング前の有効サンプルサイズ
+    fn effective_sample_size(&self, _turn: usize) -> Option<f64> {
+        None
+    }
+    // turnステップ目に推定した地図(地図を推定しない推定器は空)
+    fn landmark_estimates(&self, _turn: usize) -> Vec<LandmarkEstimate> {
+        vec![]
+    }
+    // turnステップ目に推定した動作・観測のバイアス(推定しない推定器はNone)
+    fn bias_estimate(&self, _turn: usize) -> Option<BiasEstimate> {
+        None
+    }
+    // turnステップ目に起きたリセット
+    fn reset_event(&self, _turn: usize) -> Option<ResetEvent> {
+        None
+    }
+    // 走行中にパーティクル数を変える(パーティクルを使わない推定器では何もしない)
+    fn set_particle_num(&mut self, _particle_num: usize) {}
+    // 制御指令(nu, omega)による動作更新から記録までの1ステップ
+    fn decision(&mut self, nu: f64, omega: f64, observation: &[Observation], landmarks: &[Coord]) {
+        self.predict(nu, omega);
+        self.update(observation, landmarks);
+        self.finalize();
+    }
+}
 
 #[derive(Debug, Clone, Copy)]
 pub struct Particle {
     pub pose: Pose,
-    pub weight: f64,
+    pub log_weight: f64, // 重みの対数(アンダーフローを避けるため対数で保持する)
 }
 
 impl Particle {
-    fn new(init_pose: Pose, init_weight: f64) -> Self {
+    pub fn new(init_pose: Pose, init_log_weight: f64) -> Self {
         Self {
             pose: init_pose,
-            weight: init_weight,
+            log_weight: init_log_weight,
+        }
+    }
+}
+
+// log(Σexp(x_i))を最大値で括り出して計算する
+pub fn log_sum_exp(xs: &[f64]) -> f64 {
+    let max = xs.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if !max.is_finite() {
+        return max;
+    }
+    max + xs.iter().map(|x| (x - max).exp()).sum::<f64>().ln()
+}
+
+// 対数重みを和が1になる重みに正規化する
+pub fn normalize_log_weights(log_weights: &[f64]) -> Vec<f64> {
+    let total = log_sum_exp(log_weights);
+    // 重みがすべて潰れている場合は一様な重みとみなす
+    if !total.is_finite() {
+        return vec![1.0 / log_weights.len() as f64; log_weights.len()];
+    }
+    log_weights.iter().map(|w| (w - total).exp()).collect()
+}
+
+pub fn normalized_weights(particles: &[Particle]) -> Vec<f64> {
+    let log_weights: Vec<f64> = particles.iter().map(|p| p.log_weight).collect();
+    normalize_log_weights(&log_weights)
+}
+
+// 有効サンプルサイズ 1 / Σw_i^2 (wは正規化した重み)
+pub fn effective_sample_size(weights: &[f64]) -> f64 {
+    1.0 / weights.iter().map(|w| w * w).sum::<f64>()
+}
+
+// 重み付き平均姿勢(θは円周平均)と標本共分散行列
+pub fn weighted_mean_and_cov(particles: &[Particle]) -> (Pose, Matrix) {
+    let weights = normalized_weights(particles);
+    let mut x = 0.0;
+    let mut y = 0.0;
+    let mut sin = 0.0;
+    let mut cos = 0.0;
+    for (p, w) in particles.iter().zip(weights.iter()) {
+        x += w * p.pose.coord.x;
+        y += w * p.pose.coord.y;
+        sin += w * p.pose.theta.sin();
+        cos += w * p.pose.theta.cos();
+    }
+    let mean = Pose {
+        coord: Coord { x, y },
+        theta: sin.atan2(cos),
+    };
+
+    let mut cov = Matrix::new(3, 3);
+    for (p, w) in particles.iter().zip(weights.iter()) {
+        let d = [
+            p.pose.coord.x - mean.coord.x,
+            p.pose.coord.y - mean.coord.y,
+            convert_radian_in_range(p.pose.theta - mean.theta),
+        ];
+        for i in 0..3 {
+            for j in 0..3 {
+                cov[(i, j)] += w * d[i] * d[j];
+            }
         }
     }
+    // 重み付き標本共分散の不偏補正
+    let w2 = 1.0 / effective_sample_size(&weights);
+    if 1.0 - w2 > 1e-12 {
+        cov = cov.scale(1.0 / (1.0 - w2));
+    }
+    (mean, cov)
+}
+
+// 重みの累積和
+pub fn cumulative_sum(weights: &[f64]) -> Vec<f64> {
+    let mut s = 0.0;
+    weights
+        .iter()
+        .map(|w| {
+            s += w;
+            s
+        })
+        .collect()
+}
+
+// 累積和がrを超える最初の位置(丸め誤差で末尾を越えないようにする)
+fn search(cumulative: &[f64], r: f64) -> usize {
+    cumulative
+        .partition_point(|&w| w <= r)
+        .min(cumulative.len() - 1)
+}
+
+// 系統サンプリング: 1つの乱数から等間隔にn個選ぶ
+pub fn systematic_sampling(rng: &mut Pcg64Mcg, cumulative: &[f64], n: usize) -> Vec<usize> {
+    let r = rng.gen_range(0.0..1.0);
+    (0..n)
+        .map(|i| search(cumulative, (i as f64 + r) / n as f64))
+        .collect()
+}
+
+// リサンプリングの方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resampling {
+    Systematic,
+    Multinomial,
+    Stratified,
+    Residual,
 }
 
 #[derive(Debug)]
@@ -46,6 +231,55 @@ impl MotionNoisePdf {
         let oo_noise = self.oo_pdf.sample(rng);
         (nn_noise, no_noise, on_noise, oo_noise)
     }
+    // 制御指令に雑音を加える
+    pub fn noised_control(
+        &mut self,
+        rng: &mut Pcg64Mcg,
+        nu: f64,
+        omega: f64,
+        time_interval: f64,
+    ) -> (f64, f64) {
+        let (nn_noise, no_noise, on_noise, oo_noise) = self.sample(rng);
+        let noised_nu = nu
+            + nn_noise * (nu.abs() / time_interval).sqrt()
+            + no_noise * (omega.abs() / time_interval);
+        let noised_omega = omega
+            + on_noise * (nu.abs() / time_interval).sqrt()
+            + oo_noise * (omega.abs() / time_interval);
+        (noised_nu, noised_omega)
+    }
+}
+
+// KLDサンプリングの設定
+#[derive(Debug, Clone, Copy)]
+pub struct KldConfig {
+    pub xy_bin: f64,    // x, y方向のビンの幅, m
+    pub theta_bin: f64, // 向きのビンの幅, rad
+    pub epsilon: f64,   // 許容するKLダイバージェンス
+    pub delta: f64,     // 誤差がepsilonを超える確率
+    pub min_particle_num: usize,
+    pub max_particle_num: usize,
+}
+
+impl KldConfig {
+    // 空でないビンの数kに対して必要なパーティクル数
+    pub fn required_particle_num(&self, k: usize) -> usize {
+        if k <= 1 {
+            return self.min_particle_num;
+        }
+        let k = (k - 1) as f64;
+        let z = standard_normal_quantile(1.0 - self.delta);
+        let a = 2.0 / (9.0 * k);
+        let n = k / (2.0 * self.epsilon) * (1.0 - a + a.sqrt() * z).powf(3.0);
+        (n.ceil() as usize).clamp(self.min_particle_num, self.max_particle_num)
+    }
+    fn bin(&self, pose: &Pose) -> (i64, i64, i64) {
+        (
+            (pose.coord.x / self.xy_bin).floor() as i64,
+            (pose.coord.y / self.xy_bin).floor() as i64,
+            (convert_radian_in_range(pose.theta) / self.theta_bin).floor() as i64,
+        )
+    }
 }
 
 #[derive(Debug)]
@@ -53,115 +287,401 @@ pub struct Estimator {
     pub rng: Pcg64Mcg,
     pub time_interval: f64,
     pub radius: f64,
-    pub nu: f64,
-    pub omega: f64,
-    pub prev_nu: f64,
-    pub prev_omega: f64,
     pub particles: Vec<Particle>,
     pub motion_noise_pdf: MotionNoisePdf,
     pub distance_rate_std: f64,
     pub direction_std: f64,
     pub pose_records: Vec<Vec<Pose>>,
     pub best_weight_records: Vec<usize>,
+    pub mean_records: Vec<Pose>,
+    pub cov_records: Vec<Matrix>,
+    pub kld: Option<KldConfig>,
+    pub particle_num_records: Vec<usize>,
+    pub reset: Option<Reset>,
+    pub inject_prob: f64, // Augmented MCLでランダムなパーティクルを混ぜる確率
+    pub reset_records: Vec<Option<ResetEvent>>,
+    pub resampling: Resampling,
+    pub ess_threshold: Option<f64>, // パーティクル数に対する有効サンプルサイズの比の閾値
+    pub ess_records: Vec<f64>,
+    pub association: Association,
+    pub observation_model: ObservationModel,
 }
 
 impl Estimator {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        seed: u64,
         time_interval: f64,
         init_pose: Pose,
         radius: f64,
-        nu: f64,
-        omega: f64,
         particle_num: usize,
         motion_noise_pdf: MotionNoisePdf,
         distance_rate_std: f64,
         direction_std: f64,
     ) -> Self {
         Self {
-            rng: Pcg64Mcg::seed_from_u64(0),
+            rng: Pcg64Mcg::seed_from_u64(seed),
             time_interval,
             radius,
-            nu,
-            omega,
-            prev_nu: 0.0,
-            prev_omega: 0.0,
-            particles: vec![Particle::new(init_pose, 1.0); particle_num],
+            particles: vec![Particle::new(init_pose, 0.0); particle_num],
             motion_noise_pdf,
             distance_rate_std,
             direction_std,
             pose_records: vec![vec![init_pose; particle_num]],
             best_weight_records: vec![0],
+            mean_records: vec![init_pose],
+            cov_records: vec![Matrix::new(3, 3)],
+            kld: None,
+            particle_num_records: vec![particle_num],
+            reset: None,
+            inject_prob: 0.0,
+            reset_records: vec![None],
+            resampling: Resampling::Systematic,
+            ess_threshold: None,
+            ess_records: vec![particle_num as f64],
+            association: Association::Known,
+            observation_model: ObservationModel::Gaussian,
         }
     }
+    // リサンプリングをKLDサンプリングにしてパーティクル数を適応的に変える
+    pub fn set_kld(&mut self, kld: KldConfig) {
+        self.kld = Some(kld);
+    }
+    // リサンプリングの方法と, 有効サンプルサイズが閾値を下回ったときだけリサンプリングするかを設定する
+    pub fn set_resampling(&mut self, resampling: Resampling, ess_threshold: Option<f64>) {
+        self.resampling = resampling;
+        self.ess_threshold = ess_threshold;
+    }
+    pub fn set_association(&mut self, association: Association) {
+        self.association = association;
+    }
+    // 観測の尤度のモデルを設定する(既定は正規分布)
+    pub fn set_observation_model(&mut self, observation_model: ObservationModel) {
+        self.observation_model = observation_model;
+    }
+    // 観測をランドマークに対応付ける(動作更新後のパーティクルの平均と共分散を推定姿勢とする)
+    pub fn associate(&self, observation: &[Observation], landmarks: &[Coord]) -> Vec<Observation> {
+        if self.association == Association::Known {
+            return observation.to_vec();
+        }
+        let (mean, cov) = weighted_mean_and_cov(&self.particles);
+        associate(
+            self.association,
+            &mean,
+            &cov,
+            observation,
+            landmarks,
+            self.distance_rate_std,
+            self.direction_std,
+        )
+    }
+    // 現在の重みに従って系統サンプリングでparticle_num個に選び直す
+    // KLDサンプリングでは次のリサンプリングで適応的な数に戻る
+    pub fn set_particle_num(&mut self, particle_num: usize) {
+        if particle_num == 0 || particle_num == self.particles.len() {
+            return;
+        }
+        let cumulative = cumulative_sum(&normalized_weights(&self.particles));
+        self.particles = systematic_sampling(&mut self.rng, &cumulative, particle_num)
+            .into_iter()
+            .map(|idx| Particle::new(self.particles[idx].pose, 0.0))
+            .collect();
+    }
+    // 誘拐などで推定が破綻したときのリセット方法を設定する
+    pub fn set_reset(&mut self, strategy: ResetStrategy, width: f64, height: f64) {
+        self.reset = Some(Reset::new(strategy, width, height));
+    }
     pub fn update_motion(&mut self, prev_nu: f64, prev_omega: f64) {
         let mut poses = vec![];
         for particle in self.particles.iter_mut() {
-            let (nn_noise, no_noise, on_noise, oo_noise) =
-                self.motion_noise_pdf.sample(&mut self.rng);
-            let noised_nu = prev_nu
-                + nn_noise * (prev_nu.abs() / self.time_interval).sqrt()
-                + no_noise * (prev_omega.abs() / self.time_interval);
-            let noised_omega = prev_omega
-                + on_noise * (prev_nu.abs() / self.time_interval).sqrt()
-                + oo_noise * (prev_omega.abs() / self.time_interval);
+            let (noised_nu, noised_omega) = self.motion_noise_pdf.noised_control(
+                &mut self.rng,
+                prev_nu,
+                prev_omega,
+                self.time_interval,
+            );
             particle.pose =
                 state_transition(self.time_interval, particle.pose, noised_nu, noised_omega);
-            poses.push(particle.pose.clone());
+            poses.push(particle.pose);
         }
+        self.particle_num_records.push(poses.len());
         self.pose_records.push(poses);
     }
-    pub fn updater_observation(&mut self, observation: &Vec<Observation>, landmarks: &[Coord]) {
+    pub fn updater_observation(&mut self, observation: &[Observation], landmarks: &[Coord]) {
         for particle in self.particles.iter_mut() {
             for obs in observation.iter() {
                 let mark = landmarks[obs.id];
                 let obs_particle = observe_landmark(&particle.pose, &mark, obs.id);
                 let distance_std = self.distance_rate_std * obs_particle.dist;
-                let distance_normal = Normal::new(obs_particle.dist, distance_std);
-                let direction_normal = Normal::new(obs_particle.angle, self.direction_std);
-                particle.weight *= distance_normal.pdf(obs.dist);
-                particle.weight *= direction_normal.pdf(obs.angle);
+                particle.log_weight += self.observation_model.log_likelihood(
+                    obs,
+                    &obs_particle,
+                    distance_std,
+                    self.direction_std,
+                );
+            }
+        }
+    }
+    // 観測で重みを付けた後, 平均尤度が閾値を下回っていればリセットする
+    pub fn reset_if_lost(&mut self, observation: &[Observation], landmarks: &[Coord]) {
+        let mut event = None;
+        if let (Some(reset), false) = (self.reset.as_mut(), observation.is_empty()) {
+            let log_weights: Vec<f64> = self.particles.iter().map(|p| p.log_weight).collect();
+            let log_likelihood = log_sum_exp(&log_weights) - (self.particles.len() as f64).ln();
+            let likelihood = log_likelihood.exp();
+            match reset.threshold() {
+                Some(threshold) if log_likelihood < threshold.ln() => match reset.strategy {
+                    ResetStrategy::Random { .. } => {
+                        reset.random_reset(&mut self.rng, &mut self.particles);
+                        event = Some(ResetEvent::Random);
+                    }
+                    ResetStrategy::Sensor { .. } => {
+                        let nearest = observation
+                            .iter()
+                            .min_by(|a, b| a.dist.total_cmp(&b.dist))
+                            .unwrap();
+                        reset.sensor_reset(
+                            &mut self.rng,
+                            &mut self.particles,
+                            nearest,
+                            &landmarks[nearest.id],
+                            self.distance_rate_std,
+                            self.direction_std,
+                        );
+                        event = Some(ResetEvent::Sensor);
+                    }
+                    ResetStrategy::Expansion { .. } => {
+                        reset.expansion_reset(&mut self.rng, &mut self.particles);
+                        event = Some(ResetEvent::Expansion);
+                    }
+                    ResetStrategy::Augmented { .. } => unreachable!(),
+                },
+                Some(_) => {}
+                None => self.inject_prob = reset.update_average_likelihood(likelihood),
+            }
+        }
+        // 膨張させた分布は観測で重みを付け直す
+        if event == Some(ResetEvent::Expansion) {
+            self.updater_observation(observation, landmarks);
+        }
+        self.reset_records.push(event);
+    }
+    // Augmented MCL: リサンプリング後のパーティクルを確率inject_probでランダムな姿勢に置き換える
+    fn inject_random_particles(&mut self) {
+        let reset = match &self.reset {
+            Some(reset) if self.inject_prob > 0.0 => reset,
+            _ => return,
+        };
+        let mut injected = 0;
+        for particle in self.particles.iter_mut() {
+            if self.rng.gen_range(0.0..1.0) < self.inject_prob {
+                particle.pose = reset.random_pose(&mut self.rng);
+                injected += 1;
             }
         }
+        if injected > 0 {
+            *self.reset_records.last_mut().unwrap() = Some(ResetEvent::Augmented { injected });
+        }
+        self.inject_prob = 0.0;
     }
-    // 系統サンプリング
     pub fn resampling(&mut self) {
-        let mut ws = vec![];
-        let mut s = 0.0;
-        self.particles.iter().for_each(|particle| {
-            s += particle.weight;
-            ws.push(s);
-        });
-        if s < 1e-100 {
-            ws = ws.iter().map(|x| x + 1e-100).collect();
-            s += 1e-100;
-        }
-        let step = s / self.particles.len() as f64;
-        let mut r = self.rng.gen_range(0.0..step);
-        let mut pos = 0;
-        let mut particle = vec![];
-        let mut best_particle_idx = 0;
-        let mut best_weight = std::f64::MIN;
-        while particle.len() < self.particles.len() {
-            if r < ws[pos] {
-                if best_weight < self.particles[pos].weight {
-                    best_weight = self.particles[pos].weight;
-                    best_particle_idx = particle.len();
+        let weights = normalized_weights(&self.particles);
+        let ess = effective_sample_size(&weights);
+        self.ess_records.push(ess);
+        // リサンプリング前で最も重みの大きいパーティクル
+        let best_particle_idx = (0..self.particles.len())
+            .max_by(|&i, &j| weights[i].total_cmp(&weights[j]))
+            .unwrap();
+        self.best_weight_records.push(best_particle_idx);
+        // 有効サンプルサイズが十分なら重みを持ち越してリサンプリングしない
+        // 次の観測で平均尤度が求まるように, 重みの平均が1になるよう正規化しておく
+        let n = self.particles.len() as f64;
+        if let Some(threshold) = self.ess_threshold {
+            if ess >= threshold * n {
+                for (particle, w) in self.particles.iter_mut().zip(weights.iter()) {
+                    particle.log_weight = (w * n).ln();
                 }
-                self.particles[pos].weight = 1.0;
-                particle.push(self.particles[pos]);
-                r += step;
-            } else {
-                pos += 1;
+                return;
             }
         }
-        self.particles = particle;
-        self.best_weight_records.push(best_particle_idx);
+        let cumulative = cumulative_sum(&weights);
+        let indices = match self.kld {
+            Some(kld) => self.kld_sampling(&kld, &cumulative),
+            None => match self.resampling {
+                Resampling::Systematic => {
+                    systematic_sampling(&mut self.rng, &cumulative, self.particles.len())
+                }
+                Resampling::Multinomial => self.multinomial_sampling(&cumulative),
+                Resampling::Stratified => self.stratified_sampling(&cumulative),
+                Resampling::Residual => self.residual_sampling(&weights),
+            },
+        };
+        self.particles = indices
+            .into_iter()
+            .map(|idx| Particle::new(self.particles[idx].pose, 0.0))
+            .collect();
+        self.inject_random_particles();
+    }
+    // 多項サンプリング: 独立にn回復元抽出する
+    fn multinomial_sampling(&mut self, cumulative: &[f64]) -> Vec<usize> {
+        (0..self.particles.len())
+            .map(|_| search(cumulative, self.rng.gen_range(0.0..1.0)))
+            .collect()
     }
-    pub fn decision(&mut self, observation: &Vec<Observation>, landmarks: &[Coord]) {
-        self.update_motion(self.prev_nu, self.prev_omega);
-        self.prev_nu = self.nu;
-        self.prev_omega = self.omega;
-        self.updater_observation(observation, landmarks);
+    // 層化サンプリング: [0, 1)をn等分した各区間から1つずつ選ぶ
+    fn stratified_sampling(&mut self, cumulative: &[f64]) -> Vec<usize> {
+        let n = self.particles.len();
+        (0..n)
+            .map(|i| {
+                search(
+                    cumulative,
+                    (i as f64 + self.rng.gen_range(0.0..1.0)) / n as f64,
+                )
+            })
+            .collect()
+    }
+    // 残差サンプリング: floor(n w_i)個ずつ複製し, 残りを端数の重みで多項サンプリングする
+    fn residual_sampling(&mut self, weights: &[f64]) -> Vec<usize> {
+        let n = self.particles.len();
+        let mut indices = vec![];
+        let mut residuals = vec![];
+        for (idx, w) in weights.iter().enumerate() {
+            let copies = (w * n as f64).floor();
+            // repeat_nはRust 1.82以降にしかないのでrepeatとtakeで並べる
+            #[allow(clippy::manual_repeat_n)]
+            indices.extend(std::iter::repeat(idx).take(copies as usize));
+            residuals.push(w * n as f64 - copies);
+        }
+        let total: f64 = residuals.iter().sum();
+        if indices.len() < n && total > 0.0 {
+            let residuals: Vec<f64> = residuals.iter().map(|r| r / total).collect();
+            let cumulative = cumulative_sum(&residuals);
+            while indices.len() < n {
+                indices.push(search(&cumulative, self.rng.gen_range(0.0..1.0)));
+            }
+        }
+        indices.truncate(n);
+        indices
+    }
+    // KLDサンプリング
+    // 重みに従って1つずつ復元抽出し, 占有したビンの数から求まる必要数に達するまで増やす
+    fn kld_sampling(&mut self, kld: &KldConfig, cumulative: &[f64]) -> Vec<usize> {
+        let mut bins = HashSet::new();
+        let mut indices = vec![];
+        loop {
+            let idx = search(cumulative, self.rng.gen_range(0.0..1.0));
+            bins.insert(kld.bin(&self.particles[idx].pose));
+            indices.push(idx);
+            if indices.len() >= kld.max_particle_num
+                || indices.len() >= kld.required_particle_num(bins.len())
+            {
+                break;
+            }
+        }
+        indices
+    }
+    // リサンプリング前の重みで推定姿勢と共分散を記録する
+    pub fn record_estimate(&mut self) {
+        let (mean, cov) = weighted_mean_and_cov(&self.particles);
+        self.mean_records.push(mean);
+        self.cov_records.push(cov);
+    }
+    pub fn mean(&self) -> Pose {
+        *self.mean_records.last().unwrap()
+    }
+}
+
+impl Filter for Estimator {
+    fn name(&self) -> &str {
+        "MCL"
+    }
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn predict(&mut self, nu: f64, omega: f64) {
+        self.update_motion(nu, omega);
+    }
+    fn update(&mut self, observation: &[Observation], landmarks: &[Coord]) {
+        let observation = self.associate(observation, landmarks);
+        self.updater_observation(&observation, landmarks);
+        self.reset_if_lost(&observation, landmarks);
+    }
+    fn finalize(&mut self) {
+        self.record_estimate();
         self.resampling();
     }
+    fn pose(&self) -> Pose {
+        self.mean()
+    }
+    fn estimate(&self, turn: usize) -> Pose {
+        self.mean_records[turn]
+    }
+    fn belief(&self, turn: usize) -> Belief {
+        Belief::Particles {
+            poses: self.pose_records[turn].clone(),
+            mean: self.mean_records[turn],
+            cov: self.cov_records[turn].clone(),
+        }
+    }
+    fn particle_num(&self, turn: usize) -> Option<usize> {
+        Some(self.particle_num_records[turn])
+    }
+    fn effective_sample_size(&self, turn: usize) -> Option<f64> {
+        Some(self.ess_records[turn])
+    }
+    fn reset_event(&self, turn: usize) -> Option<ResetEvent> {
+        self.reset_records[turn]
+    }
+    fn set_particle_num(&mut self, particle_num: usize) {
+        Estimator::set_particle_num(self, particle_num);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kld(min_particle_num: usize, max_particle_num: usize) -> KldConfig {
+        KldConfig {
+            xy_bin: 0.1,
+            theta_bin: 0.1,
+            epsilon: 0.05,
+            delta: 0.01,
+            min_particle_num,
+            max_particle_num,
+        }
+    }
+
+    #[test]
+    fn kld_bound_matches_chi_squared_table() {
+        // (ビンの数k, 自由度k-1のカイ二乗分布の上側1%点)
+        // Wilson-Hilfertyの近似は自由度1でも1%以内
+        let config = kld(1, 1_000_000);
+        let table = [
+            (2, 6.634896601021201),
+            (11, 23.20925115895434),
+            (51, 76.15389124901266),
+            (101, 135.80672317102662),
+        ];
+        for &(k, chi2) in table.iter() {
+            let n = config.required_particle_num(k) as f64;
+            let expected = chi2 / (2.0 * config.epsilon);
+            assert!(
+                (n - expected).abs() / expected < 0.01,
+                "k = {}: {} != {}",
+                k,
+                n,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn kld_bound_is_clamped() {
+        let config = kld(100, 500);
+        assert_eq!(config.required_particle_num(0), 100);
+        assert_eq!(config.required_particle_num(1), 100);
+        assert_eq!(config.required_particle_num(2), 100);
+        assert_eq!(config.required_particle_num(101), 500);
+    }
 }
@@ -0,0 +1,395 @@
+use rand::SeedableRng;
+use rand_pcg::Pcg64Mcg;
+
+use crate::agent::Pose;
+use crate::camera::{observe_landmark, Observation};
+use crate::common::{convert_radian_in_range, Coord};
+use crate::ekf::{jacobian_control, jacobian_observation, motion_noise_cov};
+use crate::estimator::{
+    cumulative_sum, effective_sample_size, normalize_log_weights, systematic_sampling,
+    weighted_mean_and_cov, Belief, Filter, LandmarkEstimate, MotionNoisePdf, Particle,
+};
+use crate::matrix::Matrix;
+use crate::motion::state_transition;
+use crate::normal::Normal;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FastSlamVersion {
+    // 提案分布は動作モデルのみ
+    One,
+    // 提案分布に現在の観測を取り込む
+    Two,
+}
+
+// パーティクルごとに持つランドマークのEKF
+#[derive(Debug, Clone)]
+pub struct LandmarkEkf {
+    pub id: usize,
+    pub mean: Coord,
+    pub cov: Matrix, // 2x2
+}
+
+// 姿勢と重みに加えて地図を持つパーティクル
+#[derive(Debug, Clone)]
+pub struct SlamParticle {
+    pub particle: Particle,
+    pub marks: Vec<LandmarkEkf>,
+}
+
+// FastSLAM
+#[derive(Debug)]
+pub struct FastSlam {
+    pub version: FastSlamVersion,
+    pub rng: Pcg64Mcg,
+    pub time_interval: f64,
+    pub radius: f64,
+    pub particles: Vec<SlamParticle>,
+    pub motion_noise_pdf: MotionNoisePdf,
+    pub distance_rate_std: f64,
+    pub direction_std: f64,
+    pub pose_records: Vec<Vec<Pose>>,
+    pub mean_records: Vec<Pose>,
+    pub cov_records: Vec<Matrix>,
+    pub ess_records: Vec<f64>,
+    pub map_records: Vec<Vec<LandmarkEstimate>>, // 最も重みの大きいパーティクルの地図
+    prev_poses: Vec<Pose>,                       // 動作更新前の姿勢(FastSLAM 2.0用)
+    control: (f64, f64),                         // 動作更新に使った制御指令(FastSLAM 2.0用)
+}
+
+impl FastSlam {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        version: FastSlamVersion,
+        seed: u64,
+        time_interval: f64,
+        init_pose: Pose,
+        radius: f64,
+        particle_num: usize,
+        motion_noise_pdf: MotionNoisePdf,
+        distance_rate_std: f64,
+        direction_std: f64,
+    ) -> Self {
+        let particle = SlamParticle {
+            particle: Particle::new(init_pose, 0.0),
+            marks: vec![],
+        };
+        Self {
+            version,
+            rng: Pcg64Mcg::seed_from_u64(seed),
+            time_interval,
+            radius,
+            particles: vec![particle; particle_num],
+            motion_noise_pdf,
+            distance_rate_std,
+            direction_std,
+            pose_records: vec![vec![init_pose; particle_num]],
+            mean_records: vec![init_pose],
+            cov_records: vec![Matrix::new(3, 3)],
+            ess_records: vec![particle_num as f64],
+            map_records: vec![vec![]],
+            prev_poses: vec![],
+            control: (0.0, 0.0),
+        }
+    }
+    // FastSLAM 2.0では観測後に提案分布から姿勢を選び直すため, 動作前の姿勢と制御指令も覚えておく
+    pub fn update_motion(&mut self, prev_nu: f64, prev_omega: f64) {
+        self.prev_poses = self.particles.iter().map(|p| p.particle.pose).collect();
+        self.control = (prev_nu, prev_omega);
+        for slam_particle in self.particles.iter_mut() {
+            let (noised_nu, noised_omega) = self.motion_noise_pdf.noised_control(
+                &mut self.rng,
+                prev_nu,
+                prev_omega,
+                self.time_interval,
+            );
+            let particle = &mut slam_particle.particle;
+            particle.pose =
+                state_transition(self.time_interval, particle.pose, noised_nu, noised_omega);
+        }
+    }
+    // 観測雑音の共分散行列Q
+    fn observation_noise_cov(&self, dist: f64) -> Matrix {
+        Matrix::diag(&[
+            (self.distance_rate_std * dist).powf(2.0),
+            self.direction_std.powf(2.0),
+        ])
+    }
+    pub fn updater_observation(&mut self, observation: &[Observation]) {
+        if self.version == FastSlamVersion::Two {
+            self.sample_pose_from_proposal(observation);
+        }
+        for idx in 0..self.particles.len() {
+            for obs in observation.iter() {
+                self.update_landmark(idx, obs);
+            }
+        }
+    }
+    // 観測からランドマークのEKFを更新し, パーティクルの重みに観測の尤度を掛ける
+    // 初めて観測したランドマークは観測値から初期化する
+    fn update_landmark(&mut self, idx: usize, obs: &Observation) {
+        let weighting = self.version == FastSlamVersion::One;
+        let pose = self.particles[idx].particle.pose;
+        let pos = self.particles[idx]
+            .marks
+            .iter()
+            .position(|m| m.id == obs.id);
+        let pos = match pos {
+            Some(pos) => pos,
+            None => {
+                let mark = self.init_landmark(&pose, obs);
+                self.particles[idx].marks.push(mark);
+                return;
+            }
+        };
+        let mark = &self.particles[idx].marks[pos];
+        let estimated = observe_landmark(&pose, &mark.mean, obs.id);
+        let h = jacobian_landmark(&pose, &mark.mean);
+        let q = self.observation_noise_cov(estimated.dist);
+        let s = &q + &(&(&h * &mark.cov) * &h.transpose());
+        let s_inv = match s.inverse() {
+            Some(s_inv) => s_inv,
+            None => return,
+        };
+        let innovation = Matrix::column(&[
+            obs.dist - estimated.dist,
+            convert_radian_in_range(obs.angle - estimated.angle),
+        ]);
+        let k = &(&mark.cov * &h.transpose()) * &s_inv;
+        let delta = &k * &innovation;
+        let cov = (&(&Matrix::identity(2) - &(&k * &h)) * &mark.cov).symmetrize();
+        let slam_particle = &mut self.particles[idx];
+        if weighting {
+            slam_particle.particle.log_weight += gaussian_log_likelihood(&innovation, &s, &s_inv);
+        }
+        let mark = &mut slam_particle.marks[pos];
+        mark.mean.x += delta[(0, 0)];
+        mark.mean.y += delta[(1, 0)];
+        mark.cov = cov;
+    }
+    fn init_landmark(&self, pose: &Pose, obs: &Observation) -> LandmarkEkf {
+        let phi = pose.theta + obs.angle;
+        let mean = Coord {
+            x: pose.coord.x + obs.dist * phi.cos(),
+            y: pose.coord.y + obs.dist * phi.sin(),
+        };
+        // 観測値の雑音をランドマーク位置の空間に写す
+        let g_z = Matrix::from_vec(
+            2,
+            2,
+            vec![
+                phi.cos(),
+                -obs.dist * phi.sin(),
+                phi.sin(),
+                obs.dist * phi.cos(),
+            ],
+        );
+        let q = self.observation_noise_cov(obs.dist);
+        LandmarkEkf {
+            id: obs.id,
+            mean,
+            cov: (&(&g_z * &q) * &g_z.transpose()).symmetrize(),
+        }
+    }
+    // FastSLAM 2.0の提案分布
+    // 雑音なしの動作後の姿勢と動作雑音の共分散から始め, 既知のランドマークの観測を順にカルマンフィルタで取り込み,
+    // 得られたガウス分布から姿勢を選び直す. 重みは各観測の予測分布での尤度
+    fn sample_pose_from_proposal(&mut self, observation: &[Observation]) {
+        let dt = self.time_interval;
+        let (nu, mut omega) = self.control;
+        if omega.abs() < 1e-5 {
+            omega = 1e-5;
+        }
+        let m = motion_noise_cov(&self.motion_noise_pdf, nu, omega, dt);
+        let mut standard_normal = Normal::new(0.0, 1.0);
+        for idx in 0..self.particles.len() {
+            let prev_pose = self.prev_poses[idx];
+            let mut mean = state_transition(dt, prev_pose, nu, omega);
+            let a = jacobian_control(nu, omega, dt, prev_pose.theta);
+            let mut cov = &(&a * &m) * &a.transpose();
+            let mut log_weight = 0.0;
+            let mut observed = false;
+            for obs in observation.iter() {
+                let mark = match self.particles[idx].marks.iter().find(|m| m.id == obs.id) {
+                    Some(mark) => mark,
+                    None => continue,
+                };
+                observed = true;
+                let estimated = observe_landmark(&mean, &mark.mean, obs.id);
+                let h_x = jacobian_observation(&mean, &mark.mean);
+                let h_m = jacobian_landmark(&mean, &mark.mean);
+                let q = self.observation_noise_cov(estimated.dist);
+                let s = &q + &(&(&h_m * &mark.cov) * &h_m.transpose());
+                let sigma = &s + &(&(&h_x * &cov) * &h_x.transpose());
+                let sigma_inv = match sigma.inverse() {
+                    Some(sigma_inv) => sigma_inv,
+                    None => continue,
+                };
+                let innovation = Matrix::column(&[
+                    obs.dist - estimated.dist,
+                    convert_radian_in_range(obs.angle - estimated.angle),
+                ]);
+                log_weight += gaussian_log_likelihood(&innovation, &sigma, &sigma_inv);
+                let k = &(&cov * &h_x.transpose()) * &sigma_inv;
+                let delta = &k * &innovation;
+                mean.coord.x += delta[(0, 0)];
+                mean.coord.y += delta[(1, 0)];
+                mean.theta += delta[(2, 0)];
+                cov = (&(&Matrix::identity(3) - &(&k * &h_x)) * &cov).symmetrize();
+            }
+            let particle = &mut self.particles[idx].particle;
+            // 既知のランドマークを観測していなければ動作モデルから選んだ姿勢のままにする
+            if !observed {
+                continue;
+            }
+            particle.log_weight += log_weight;
+            // 動作雑音の共分散は階数が2なので, コレスキー分解できるように対角を底上げする
+            let l = match (&cov + &Matrix::diag(&[1e-12; 3])).cholesky() {
+                Some(l) => l,
+                None => {
+                    particle.pose = mean;
+                    continue;
+                }
+            };
+            let z = Matrix::column(&[
+                standard_normal.sample(&mut self.rng),
+                standard_normal.sample(&mut self.rng),
+                standard_normal.sample(&mut self.rng),
+            ]);
+            let noise = &l * &z;
+            particle.pose = Pose {
+                coord: Coord {
+                    x: mean.coord.x + noise[(0, 0)],
+                    y: mean.coord.y + noise[(1, 0)],
+                },
+                theta: convert_radian_in_range(mean.theta + noise[(2, 0)]),
+            };
+        }
+    }
+    pub fn resampling(&mut self) {
+        let log_weights: Vec<f64> = self
+            .particles
+            .iter()
+            .map(|p| p.particle.log_weight)
+            .collect();
+        let weights = normalize_log_weights(&log_weights);
+        self.ess_records.push(effective_sample_size(&weights));
+        let indices = systematic_sampling(
+            &mut self.rng,
+            &cumulative_sum(&weights),
+            self.particles.len(),
+        );
+        self.particles = indices
+            .into_iter()
+            .map(|idx| {
+                let mut p = self.particles[idx].clone();
+                p.particle.log_weight = 0.0;
+                p
+            })
+            .collect();
+    }
+    // 現在の重みに従って系統サンプリングでparticle_num個に選び直す(地図も複製する)
+    pub fn set_particle_num(&mut self, particle_num: usize) {
+        if particle_num == 0 || particle_num == self.particles.len() {
+            return;
+        }
+        let log_weights: Vec<f64> = self
+            .particles
+            .iter()
+            .map(|p| p.particle.log_weight)
+            .collect();
+        let cumulative = cumulative_sum(&normalize_log_weights(&log_weights));
+        self.particles = systematic_sampling(&mut self.rng, &cumulative, particle_num)
+            .into_iter()
+            .map(|idx| {
+                let mut p = self.particles[idx].clone();
+                p.particle.log_weight = 0.0;
+                p
+            })
+            .collect();
+    }
+    // リサンプリング前の重みで推定姿勢と共分散, 最も重みの大きいパーティクルの地図を記録する
+    pub fn record(&mut self) {
+        let particles: Vec<Particle> = self.particles.iter().map(|p| p.particle).collect();
+        let (mean, cov) = weighted_mean_and_cov(&particles);
+        self.pose_records
+            .push(particles.iter().map(|p| p.pose).collect());
+        self.mean_records.push(mean);
+        self.cov_records.push(cov);
+        let best = self
+            .particles
+            .iter()
+            .max_by(|a, b| a.particle.log_weight.total_cmp(&b.particle.log_weight))
+            .unwrap();
+        self.map_records.push(
+            best.marks
+                .iter()
+                .map(|m| LandmarkEstimate {
+                    id: m.id,
+                    coord: m.mean,
+                    cov: m.cov.clone(),
+                })
+                .collect(),
+        );
+    }
+}
+
+impl Filter for FastSlam {
+    fn name(&self) -> &str {
+        match self.version {
+            FastSlamVersion::One => "FastSLAM1",
+            FastSlamVersion::Two => "FastSLAM2",
+        }
+    }
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn predict(&mut self, nu: f64, omega: f64) {
+        self.update_motion(nu, omega);
+    }
+    // 地図は推定するので真のランドマーク位置は使わない
+    fn update(&mut self, observation: &[Observation], _landmarks: &[Coord]) {
+        self.updater_observation(observation);
+    }
+    fn finalize(&mut self) {
+        self.record();
+        self.resampling();
+    }
+    fn pose(&self) -> Pose {
+        *self.mean_records.last().unwrap()
+    }
+    fn estimate(&self, turn: usize) -> Pose {
+        self.mean_records[turn]
+    }
+    fn belief(&self, turn: usize) -> Belief {
+        Belief::Particles {
+            poses: self.pose_records[turn].clone(),
+            mean: self.mean_records[turn],
+            cov: self.cov_records[turn].clone(),
+        }
+    }
+    fn particle_num(&self, turn: usize) -> Option<usize> {
+        Some(self.pose_records[turn].len())
+    }
+    fn effective_sample_size(&self, turn: usize) -> Option<f64> {
+        Some(self.ess_records[turn])
+    }
+    fn landmark_estimates(&self, turn: usize) -> Vec<LandmarkEstimate> {
+        self.map_records[turn].clone()
+    }
+    fn set_particle_num(&mut self, particle_num: usize) {
+        FastSlam::set_particle_num(self, particle_num);
+    }
+}
+
+// 観測関数のランドマーク位置に関するヤコビアン(姿勢に関するヤコビアンのx, y成分の符号を反転したもの)
+fn jacobian_landmark(pose: &Pose, mark: &Coord) -> Matrix {
+    jacobian_observation(pose, mark)
+        .block(0, 0, 2, 2)
+        .scale(-1.0)
+}
+
+// 2次元ガウス分布N(0, S)でのイノベーションvの対数尤度(normal.rsと同じく定数は省く)
+fn gaussian_log_likelihood(v: &Matrix, s: &Matrix, s_inv: &Matrix) -> f64 {
+    let mahalanobis = (&(&v.transpose() * s_inv) * v)[(0, 0)];
+    -0.5 * mahalanobis - 0.5 * s.determinant().ln()
+}
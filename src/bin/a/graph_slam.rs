@@ -0,0 +1,534 @@
+use crate::agent::Pose;
+use crate::camera::{observe_landmark, Observation};
+use crate::common::{convert_radian_in_range, Coord};
+use crate::ekf::{jacobian_control, jacobian_observation, jacobian_state, motion_noise_cov};
+use crate::estimator::{LandmarkEstimate, MotionNoisePdf};
+use crate::matrix::Matrix;
+use crate::motion::state_transition;
+use crate::scenario::Scenario;
+use crate::simulate;
+use crate::sparse::SparseSymmetric;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphSlamMethod {
+    GaussNewton,
+    LevenbergMarquardt,
+}
+
+// 走行後に記録全体から軌跡と地図を求めるグラフベースSLAM
+#[derive(Debug)]
+pub struct GraphSlam {
+    pub method: GraphSlamMethod,
+    pub max_iterations: usize,
+    pub tolerance: f64, // χ²の相対的な減少量または更新量がこれを下回ったら収束とみなす
+    pub time_interval: f64,
+    pub init_pose: Pose,
+    pub motion_noise_pdf: MotionNoisePdf,
+    pub distance_rate_std: f64,
+    pub direction_std: f64,
+}
+
+// 動作のエッジ: poses[from]から制御指令controlで動いた姿勢とposes[from + 1]の差
+#[derive(Debug)]
+struct OdometryEdge {
+    from: usize,
+    control: (f64, f64),
+    info: Matrix, // 3x3
+}
+
+// 観測のエッジ: poses[pose]からmarks[mark]を観測した値obsと予測値の差
+#[derive(Debug)]
+struct ObservationEdge {
+    pose: usize,
+    mark: usize,
+    obs: Observation,
+    info: Matrix, // 2x2
+}
+
+// エッジの種類ごとの残差の統計
+// rmsはodometryでは(位置[m], 向き[rad]), observationでは(距離[m], 方角[rad])
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResidualStats {
+    pub count: usize,
+    pub chi2: f64,
+    pub rms: (f64, f64),
+}
+
+#[derive(Debug)]
+pub struct GraphSlamResult {
+    pub poses: Vec<Pose>,
+    pub pose_covs: Vec<Matrix>,
+    pub map: Vec<LandmarkEstimate>,
+    pub iterations: usize,
+    pub converged: bool,
+    pub initial_chi2: f64,
+    pub final_chi2: f64,
+    pub odometry: ResidualStats,
+    pub observation: ResidualStats,
+}
+
+// 初期姿勢を固定するための事前情報の重み
+const PRIOR_INFO: f64 = 1e8;
+// 動作雑音の共分散は階数が2なので, 逆行列が求まるように対角を底上げする
+const ODOMETRY_COV_FLOOR: f64 = 1e-6;
+
+impl GraphSlam {
+    // controls[t]はt番目からt+1番目の姿勢への制御指令, obs_records[t]はt番目の姿勢での観測
+    pub fn optimize(
+        &self,
+        controls: &[(f64, f64)],
+        obs_records: &[Vec<Observation>],
+    ) -> GraphSlamResult {
+        let pose_num = controls.len() + 1;
+        let dt = self.time_interval;
+
+        // 制御指令だけで求めた軌跡を初期値にする
+        let mut poses = vec![self.init_pose];
+        for &(nu, omega) in controls.iter() {
+            poses.push(state_transition(dt, *poses.last().unwrap(), nu, omega));
+        }
+
+        let mut odometry_edges = vec![];
+        for (from, &(nu, omega)) in controls.iter().enumerate() {
+            let omega_j = clamp_omega(omega);
+            let m = motion_noise_cov(&self.motion_noise_pdf, nu, omega_j, dt);
+            let a = jacobian_control(nu, omega_j, dt, poses[from].theta);
+            let cov = &(&(&a * &m) * &a.transpose()) + &Matrix::diag(&[ODOMETRY_COV_FLOOR; 3]);
+            odometry_edges.push(OdometryEdge {
+                from,
+                control: (nu, omega),
+                info: cov.inverse().unwrap(),
+            });
+        }
+
+        // ランドマークは初めて観測したときの値で初期化する
+        let mut mark_ids: Vec<usize> = vec![];
+        let mut marks: Vec<Coord> = vec![];
+        let mut observation_edges = vec![];
+        for (t, observation) in obs_records.iter().enumerate().take(pose_num) {
+            for obs in observation.iter() {
+                let mark = match mark_ids.iter().position(|&id| id == obs.id) {
+                    Some(mark) => mark,
+                    None => {
+                        let phi = poses[t].theta + obs.angle;
+                        mark_ids.push(obs.id);
+                        marks.push(Coord {
+                            x: poses[t].coord.x + obs.dist * phi.cos(),
+                            y: poses[t].coord.y + obs.dist * phi.sin(),
+                        });
+                        marks.len() - 1
+                    }
+                };
+                let q = Matrix::diag(&[
+                    (self.distance_rate_std * obs.dist).powf(2.0),
+                    self.direction_std.powf(2.0),
+                ]);
+                observation_edges.push(ObservationEdge {
+                    pose: t,
+                    mark,
+                    obs: *obs,
+                    info: q.inverse().unwrap(),
+                });
+            }
+        }
+
+        let graph = Graph {
+            time_interval: dt,
+            init_pose: self.init_pose,
+            pose_num,
+            odometry_edges,
+            observation_edges,
+        };
+        let mut x = graph.to_state(&poses, &marks);
+        let mut chi2 = graph.chi2(&x);
+        let initial_chi2 = chi2;
+        let mut lambda = match self.method {
+            GraphSlamMethod::GaussNewton => 0.0,
+            GraphSlamMethod::LevenbergMarquardt => 1e-3,
+        };
+        let mut iterations = 0;
+        let mut converged = false;
+        while iterations < self.max_iterations && !converged {
+            iterations += 1;
+            let (h, b) = graph.linearize(&x);
+            let neg_b: Vec<f64> = b.iter().map(|v| -v).collect();
+            // LM法ではχ²が減るまで減衰係数を大きくしてやり直す
+            let step = loop {
+                let mut damped = h.clone();
+                for (i, d) in h.diagonal().iter().enumerate() {
+                    damped.add(i, i, lambda * d);
+                }
+                let dx = damped.cholesky().map(|l| l.solve(&neg_b));
+                let dx = match (dx, self.method) {
+                    (Some(dx), _) => dx,
+                    (None, GraphSlamMethod::LevenbergMarquardt) if lambda < 1e10 => {
+                        lambda *= 10.0;
+                        continue;
+                    }
+                    (None, _) => break None,
+                };
+                let x_new = graph.apply(&x, &dx);
+                let chi2_new = graph.chi2(&x_new);
+                match self.method {
+                    GraphSlamMethod::GaussNewton => break Some((x_new, chi2_new, dx)),
+                    GraphSlamMethod::LevenbergMarquardt if chi2_new < chi2 => {
+                        lambda = (lambda / 10.0).max(1e-12);
+                        break Some((x_new, chi2_new, dx));
+                    }
+                    GraphSlamMethod::LevenbergMarquardt if lambda < 1e10 => lambda *= 10.0,
+                    GraphSlamMethod::LevenbergMarquardt => break None,
+                }
+            };
+            let (x_new, chi2_new, dx) = match step {
+                Some(step) => step,
+                // LM法ではこれ以上χ²を減らせないので収束とみなし, ガウス・ニュートン法では解けずに終わる
+                None => {
+                    converged = self.method == GraphSlamMethod::LevenbergMarquardt;
+                    break;
+                }
+            };
+            let max_dx = dx.iter().fold(0.0_f64, |m, v| m.max(v.abs()));
+            converged = (chi2 - chi2_new).abs() <= self.tolerance * chi2.max(1e-12)
+                || max_dx <= self.tolerance;
+            x = x_new;
+            chi2 = chi2_new;
+        }
+
+        // 情報行列の逆行列の対角ブロックが各姿勢・ランドマークの周辺共分散
+        let (h, _) = graph.linearize(&x);
+        let factor = h.cholesky();
+        let marginal = |offset: usize, dim: usize| -> Matrix {
+            let mut cov = Matrix::new(dim, dim);
+            if let Some(l) = &factor {
+                for j in 0..dim {
+                    let mut e = vec![0.0; x.len()];
+                    e[offset + j] = 1.0;
+                    let col = l.solve(&e);
+                    for i in 0..dim {
+                        cov[(i, j)] = col[offset + i];
+                    }
+                }
+            }
+            cov.symmetrize()
+        };
+        let (poses, marks) = graph.split_state(&x);
+        let pose_covs = (0..pose_num).map(|t| marginal(3 * t, 3)).collect();
+        let map = marks
+            .iter()
+            .zip(mark_ids.iter())
+            .enumerate()
+            .map(|(j, (&coord, &id))| LandmarkEstimate {
+                id,
+                coord,
+                cov: marginal(3 * pose_num + 2 * j, 2),
+            })
+            .collect();
+        let (odometry, observation) = graph.residual_stats(&x);
+        GraphSlamResult {
+            poses,
+            pose_covs,
+            map,
+            iterations,
+            converged,
+            initial_chi2,
+            final_chi2: chi2,
+            odometry,
+            observation,
+        }
+    }
+}
+
+// ω=0ではヤコビアンが発散するため微小値に置き換える
+fn clamp_omega(omega: f64) -> f64 {
+    if omega.abs() < 1e-5 {
+        1e-5
+    } else {
+        omega
+    }
+}
+
+// 状態ベクトルは(x0, y0, θ0, x1, ..., m0x, m0y, m1x, ...)
+#[derive(Debug)]
+struct Graph {
+    time_interval: f64,
+    init_pose: Pose,
+    pose_num: usize,
+    odometry_edges: Vec<OdometryEdge>,
+    observation_edges: Vec<ObservationEdge>,
+}
+
+impl Graph {
+    fn to_state(&self, poses: &[Pose], marks: &[Coord]) -> Vec<f64> {
+        let mut x = vec![];
+        for pose in poses.iter() {
+            x.extend([pose.coord.x, pose.coord.y, pose.theta]);
+        }
+        for mark in marks.iter() {
+            x.extend([mark.x, mark.y]);
+        }
+        x
+    }
+    fn split_state(&self, x: &[f64]) -> (Vec<Pose>, Vec<Coord>) {
+        let poses = (0..self.pose_num).map(|t| pose_at(x, t)).collect();
+        let marks = x[3 * self.pose_num..]
+            .chunks(2)
+            .map(|m| Coord { x: m[0], y: m[1] })
+            .collect();
+        (poses, marks)
+    }
+    fn apply(&self, x: &[f64], dx: &[f64]) -> Vec<f64> {
+        let mut x: Vec<f64> = x.iter().zip(dx.iter()).map(|(a, b)| a + b).collect();
+        for t in 0..self.pose_num {
+            x[3 * t + 2] = convert_radian_in_range(x[3 * t + 2]);
+        }
+        x
+    }
+    fn prior_residual(&self, x: &[f64]) -> Matrix {
+        let pose = pose_at(x, 0);
+        Matrix::column(&[
+            pose.coord.x - self.init_pose.coord.x,
+            pose.coord.y - self.init_pose.coord.y,
+            convert_radian_in_range(pose.theta - self.init_pose.theta),
+        ])
+    }
+    fn odometry_residual(&self, x: &[f64], edge: &OdometryEdge) -> Matrix {
+        let (nu, omega) = edge.control;
+        let predicted = state_transition(self.time_interval, pose_at(x, edge.from), nu, omega);
+        let pose = pose_at(x, edge.from + 1);
+        Matrix::column(&[
+            pose.coord.x - predicted.coord.x,
+            pose.coord.y - predicted.coord.y,
+            convert_radian_in_range(pose.theta - predicted.theta),
+        ])
+    }
+    fn observation_residual(&self, x: &[f64], edge: &ObservationEdge) -> Matrix {
+        let estimated = observe_landmark(
+            &pose_at(x, edge.pose),
+            &self.mark_at(x, edge.mark),
+            edge.obs.id,
+        );
+        Matrix::column(&[
+            edge.obs.dist - estimated.dist,
+            convert_radian_in_range(edge.obs.angle - estimated.angle),
+        ])
+    }
+    fn mark_at(&self, x: &[f64], j: usize) -> Coord {
+        let offset = 3 * self.pose_num + 2 * j;
+        Coord {
+            x: x[offset],
+            y: x[offset + 1],
+        }
+    }
+    fn chi2(&self, x: &[f64]) -> f64 {
+        let prior = self.prior_residual(x);
+        let mut chi2 = PRIOR_INFO * (&prior.transpose() * &prior)[(0, 0)];
+        for edge in self.odometry_edges.iter() {
+            chi2 += quadratic_form(&self.odometry_residual(x, edge), &edge.info);
+        }
+        for edge in self.observation_edges.iter() {
+            chi2 += quadratic_form(&self.observation_residual(x, edge), &edge.info);
+        }
+        chi2
+    }
+    // χ²を線形化した正規方程式の係数H = Σ J^T Ω J と b = Σ J^T Ω e
+    fn linearize(&self, x: &[f64]) -> (SparseSymmetric, Vec<f64>) {
+        let mut h = SparseSymmetric::new(x.len());
+        let mut b = vec![0.0; x.len()];
+        add_edge(
+            &mut h,
+            &mut b,
+            &[(0, Matrix::identity(3))],
+            &self.prior_residual(x),
+            &Matrix::diag(&[PRIOR_INFO; 3]),
+        );
+        for edge in self.odometry_edges.iter() {
+            let (nu, omega) = edge.control;
+            let from = pose_at(x, edge.from);
+            let f = jacobian_state(nu, clamp_omega(omega), self.time_interval, from.theta);
+            add_edge(
+                &mut h,
+                &mut b,
+                &[
+                    (3 * edge.from, f.scale(-1.0)),
+                    (3 * (edge.from + 1), Matrix::identity(3)),
+                ],
+                &self.odometry_residual(x, edge),
+                &edge.info,
+            );
+        }
+        for edge in self.observation_edges.iter() {
+            let h_x = jacobian_observation(&pose_at(x, edge.pose), &self.mark_at(x, edge.mark));
+            add_edge(
+                &mut h,
+                &mut b,
+                &[
+                    (3 * edge.pose, h_x.scale(-1.0)),
+                    (3 * self.pose_num + 2 * edge.mark, h_x.block(0, 0, 2, 2)),
+                ],
+                &self.observation_residual(x, edge),
+                &edge.info,
+            );
+        }
+        (h, b)
+    }
+    fn residual_stats(&self, x: &[f64]) -> (ResidualStats, ResidualStats) {
+        let mut odometry = ResidualStats::default();
+        let mut sum = (0.0, 0.0);
+        for edge in self.odometry_edges.iter() {
+            let e = self.odometry_residual(x, edge);
+            odometry.count += 1;
+            odometry.chi2 += quadratic_form(&e, &edge.info);
+            sum.0 += e[(0, 0)].powf(2.0) + e[(1, 0)].powf(2.0);
+            sum.1 += e[(2, 0)].powf(2.0);
+        }
+        odometry.rms = rms(sum, odometry.count);
+
+        let mut observation = ResidualStats::default();
+        let mut sum = (0.0, 0.0);
+        for edge in self.observation_edges.iter() {
+            let e = self.observation_residual(x, edge);
+            observation.count += 1;
+            observation.chi2 += quadratic_form(&e, &edge.info);
+            sum.0 += e[(0, 0)].powf(2.0);
+            sum.1 += e[(1, 0)].powf(2.0);
+        }
+        observation.rms = rms(sum, observation.count);
+        (odometry, observation)
+    }
+}
+
+fn pose_at(x: &[f64], t: usize) -> Pose {
+    Pose {
+        coord: Coord {
+            x: x[3 * t],
+            y: x[3 * t + 1],
+        },
+        theta: x[3 * t + 2],
+    }
+}
+
+fn rms(sum: (f64, f64), count: usize) -> (f64, f64) {
+    let n = count.max(1) as f64;
+    ((sum.0 / n).sqrt(), (sum.1 / n).sqrt())
+}
+
+// e^T Ω e
+fn quadratic_form(e: &Matrix, info: &Matrix) -> f64 {
+    (&(&e.transpose() * info) * e)[(0, 0)]
+}
+
+// 残差eの各変数ブロック(先頭の添字, ヤコビアン)への寄与をHとbに足し込む
+fn add_edge(
+    h: &mut SparseSymmetric,
+    b: &mut [f64],
+    blocks: &[(usize, Matrix)],
+    e: &Matrix,
+    info: &Matrix,
+) {
+    for (offset_i, j_i) in blocks.iter() {
+        let jt_info = &j_i.transpose() * info;
+        let b_i = &jt_info * e;
+        for r in 0..b_i.rows {
+            b[offset_i + r] += b_i[(r, 0)];
+        }
+        for (offset_j, j_j) in blocks.iter() {
+            // 対称なので下三角部分だけ足す
+            if offset_j > offset_i {
+                continue;
+            }
+            let h_ij = &jt_info * j_j;
+            for r in 0..h_ij.rows {
+                for c in 0..h_ij.cols {
+                    if offset_i + r >= offset_j + c {
+                        h.add(offset_i + r, offset_j + c, h_ij[(r, c)]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// シード0で走行し, 記録全体をグラフベースSLAMで最適化した結果とオンラインの推定器の誤差を出力する
+pub fn run(scenario: &Scenario) {
+    let (input, output) = simulate(scenario, 0);
+    let agent = &output.agents[0];
+    let max_turn = input.max_turn();
+    let controls = agent.control_records[1..=max_turn].to_vec();
+    let graph_slam = scenario.build_graph_slam(&input);
+    let result = graph_slam.optimize(&controls, &agent.obs_records);
+
+    println!(
+        "GraphSLAM ({:?}): {} iterations, {}",
+        graph_slam.method,
+        result.iterations,
+        if result.converged {
+            "converged"
+        } else {
+            "not converged"
+        }
+    );
+    println!(
+        "chi2: {:.3} -> {:.3}",
+        result.initial_chi2, result.final_chi2
+    );
+    println!(
+        "{:<12} {:>8} {:>12} {:>24}",
+        "edges", "count", "chi2/edge", "residual RMS"
+    );
+    println!(
+        "{:<12} {:>8} {:>12.3} {:>11.4} m / {:>6.3} deg",
+        "odometry",
+        result.odometry.count,
+        result.odometry.chi2 / result.odometry.count.max(1) as f64,
+        result.odometry.rms.0,
+        result.odometry.rms.1.to_degrees()
+    );
+    println!(
+        "{:<12} {:>8} {:>12.3} {:>11.4} m / {:>6.3} deg",
+        "observation",
+        result.observation.count,
+        result.observation.chi2 / result.observation.count.max(1) as f64,
+        result.observation.rms.0,
+        result.observation.rms.1.to_degrees()
+    );
+    for mark in result.map.iter() {
+        let truth = input.landmarks[mark.id];
+        println!(
+            "landmark {}: ({:.3}, {:.3}), error {:.3} m",
+            mark.id,
+            mark.coord.x,
+            mark.coord.y,
+            ((mark.coord.x - truth.x).powf(2.0) + (mark.coord.y - truth.y).powf(2.0)).sqrt()
+        );
+    }
+
+    // 真の軌跡とGraphSLAMの軌跡それぞれに対する位置誤差のRMSE
+    let position_rmse = |estimate: &dyn Fn(usize) -> Pose, reference: &[Pose]| -> f64 {
+        let se: f64 = (1..=max_turn)
+            .map(|t| {
+                let (e, r) = (estimate(t), reference[t]);
+                (e.coord.x - r.coord.x).powf(2.0) + (e.coord.y - r.coord.y).powf(2.0)
+            })
+            .sum();
+        (se / max_turn.max(1) as f64).sqrt()
+    };
+    let truth = &agent.pose_records;
+    println!(
+        "{:<12} {:>18} {:>18}",
+        "", "pos RMSE [m]", "vs GraphSLAM [m]"
+    );
+    println!(
+        "{:<12} {:>18.3} {:>18}",
+        "GraphSLAM",
+        position_rmse(&|t| result.poses[t], truth),
+        "-"
+    );
+    for estimator in output.estimators.iter() {
+        println!(
+            "{:<12} {:>18.3} {:>18.3}",
+            estimator.name(),
+            position_rmse(&|t| estimator.estimate(t), truth),
+            position_rmse(&|t| estimator.estimate(t), &result.poses),
+        );
+    }
+}
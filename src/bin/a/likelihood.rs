@@ -0,0 +1,88 @@
+use std::f64::consts::PI;
+
+use crate::camera::{Camera, Observation};
+use crate::common::convert_radian_in_range;
+use crate::estimator::log_sum_exp;
+use crate::normal::Normal;
+
+// パーティクルの重み付けに使う観測1つあたりの尤度のモデル
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObservationModel {
+    // 距離と方角の誤差をそれぞれ正規分布とする
+    Gaussian,
+    // 正規分布の代わりに自由度dofのt分布を使う
+    // 裾が重いので, 外れた観測1つで正しいパーティクルの重みが潰れにくい
+    StudentT {
+        dof: f64,
+    },
+    // ビームモデルのように次の3つの混合分布とする(重みは合計が1になるよう正規化する)
+    // hit: 正しい観測(正規分布)
+    // short: 手前の遮蔽物による短い観測(予測距離までの指数分布, 方角は正規分布)
+    // rand: ファントムなどの誤検出(観測範囲内の一様分布)
+    Mixture {
+        z_hit: f64,
+        z_short: f64,
+        z_rand: f64,
+        lambda_short: f64, // 1/m
+    },
+}
+
+impl ObservationModel {
+    // 姿勢から予測した観測estimatedに対する実際の観測obsの対数尤度
+    // 正規分布の場合と尺度を揃えるため, normal_log_pdfと同じく次元ごとの1/√(2π)は省く
+    pub fn log_likelihood(
+        &self,
+        obs: &Observation,
+        estimated: &Observation,
+        distance_std: f64,
+        direction_std: f64,
+    ) -> f64 {
+        let angle_error = convert_radian_in_range(obs.angle - estimated.angle);
+        let distance_normal = Normal::new(estimated.dist, distance_std);
+        let direction_normal = Normal::new(0.0, direction_std);
+        match *self {
+            ObservationModel::Gaussian => {
+                distance_normal.log_pdf(obs.dist) + direction_normal.log_pdf(angle_error)
+            }
+            ObservationModel::StudentT { dof } => {
+                student_t_log_pdf(obs.dist - estimated.dist, distance_std, dof)
+                    + student_t_log_pdf(angle_error, direction_std, dof)
+            }
+            ObservationModel::Mixture {
+                z_hit,
+                z_short,
+                z_rand,
+                lambda_short,
+            } => {
+                let log_sqrt_2pi = (2.0 * PI).sqrt().ln();
+                // 各成分は正規化された密度で計算し, 最後に省いた定数を戻す
+                let direction_log_pdf = direction_normal.log_pdf(angle_error) - log_sqrt_2pi;
+                let hit = distance_normal.log_pdf(obs.dist) - log_sqrt_2pi + direction_log_pdf;
+                let short = if obs.dist <= estimated.dist {
+                    let eta = 1.0 / (1.0 - (-lambda_short * estimated.dist).exp());
+                    (eta * lambda_short).ln() - lambda_short * obs.dist + direction_log_pdf
+                } else {
+                    f64::NEG_INFINITY
+                };
+                let distance_range = Camera::VIS_DISTANCE_RANGE;
+                let direction_range = Camera::VIS_DIRECTION_RANGE;
+                let rand = -((distance_range.end - distance_range.start)
+                    * (direction_range.end - direction_range.start))
+                    .ln();
+                let total = z_hit + z_short + z_rand;
+                log_sum_exp(&[
+                    (z_hit / total).ln() + hit,
+                    (z_short / total).ln() + short,
+                    (z_rand / total).ln() + rand,
+                ]) + 2.0 * log_sqrt_2pi
+            }
+        }
+    }
+}
+
+// 平均0, 尺度scale, 自由度dofのt分布の対数密度(scaleに依らない定数は省く)
+// dofが大きくなるとnormal_log_pdfに一致する
+fn student_t_log_pdf(x: f64, scale: f64, dof: f64) -> f64 {
+    let v = x / scale;
+    -0.5 * (dof + 1.0) * (1.0 + v * v / dof).ln() - scale.ln()
+}
@@ -0,0 +1,631 @@
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use crate::agent::{Agent, Pose};
+use crate::camera::Observation;
+use crate::common::Coord;
+use crate::estimator::{Belief, BiasEstimate, Filter, LandmarkEstimate};
+use crate::matrix::Matrix;
+use crate::reset::ResetEvent;
+use crate::world::{Input, Output};
+
+// 走行ログの書き出しと読み込み
+//
+// 拡張子が.jsonlならJSON Lines形式で1ファイルに書き出す(replayで再生できるのはこちら)
+// 1行目がheader, 続いてturnの昇順に各turnのstepと推定器の数だけのestimateが並ぶ
+// 姿勢は[x, y, theta], 共分散行列は行優先で並べる
+//   {"type": "header", "version": 1, "agent_id", "time_span", "time_interval", "width", "height",
+//    "landmarks": [[x, y], ...], "init_pose", "radius", "nu", "omega",
+//    "estimators": [{"name", "radius"}, ...]}
+//   {"type": "step", "turn", "pose", "control": [nu, omega] (t=0はnull),
+//    "observations": [{"id" (idなしはnull), "dist", "angle"}, ...]}
+//   {"type": "estimate", "estimator" (headerのestimatorsの添字), "turn", "mean", "cov" (3x3),
+//    "particles": [pose, ...] (パーティクルを使わない推定器と書き出さない場合はnull),
+//    "particle_num", "ess", "landmarks": [{"id", "x", "y", "cov" (2x2)}, ...],
+//    "bias": {"mean", "std"} (BiasEstimateと同じ並び), "reset": {"kind", "injected"}}
+//
+// それ以外のパスにはディレクトリを作り, 表ごとにCSVを書き出す(値がない欄は空)
+// estimatorは推定器の添字(estimator.kindsの順)で, 同じ種類の推定器が複数あっても区別できる
+//   landmarks.csv: id,x,y
+//   truth.csv: turn,x,y,theta,nu,omega
+//   observations.csv: turn,id,dist,angle
+//   estimates.csv: estimator,name,turn,x,y,theta,cov_xx,cov_xy,cov_xtheta,cov_yy,cov_ytheta,
+//                  cov_thetatheta,particle_num,ess,reset
+//   particles.csv: estimator,name,turn,x,y,theta
+//   landmark_estimates.csv: estimator,name,turn,id,x,y,cov_xx,cov_xy,cov_yy
+
+const VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record {
+    Header(Header),
+    Step(Step),
+    Estimate(Estimate),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    agent_id: u64,
+    time_span: f64,
+    time_interval: f64,
+    width: usize,
+    height: usize,
+    landmarks: Vec<[f64; 2]>,
+    init_pose: [f64; 3],
+    radius: f64,
+    nu: f64,
+    omega: f64,
+    estimators: Vec<EstimatorInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EstimatorInfo {
+    name: String,
+    radius: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Step {
+    turn: usize,
+    pose: [f64; 3],
+    control: Option<[f64; 2]>, // 直前のturnからこのturnまでに与えた制御指令
+    observations: Vec<ObservationRecord>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ObservationRecord {
+    id: Option<usize>,
+    dist: f64,
+    angle: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Estimate {
+    estimator: usize,
+    turn: usize,
+    mean: [f64; 3],
+    cov: [f64; 9],
+    particles: Option<Vec<[f64; 3]>>,
+    particle_num: Option<usize>,
+    ess: Option<f64>,
+    #[serde(default)]
+    landmarks: Vec<LandmarkRecord>,
+    bias: Option<BiasRecord>,
+    reset: Option<ResetRecord>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LandmarkRecord {
+    id: usize,
+    x: f64,
+    y: f64,
+    cov: [f64; 4],
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BiasRecord {
+    mean: [f64; 4],
+    std: [f64; 4],
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum ResetRecord {
+    Random,
+    Sensor,
+    Expansion,
+    Augmented { injected: usize },
+}
+
+#[derive(Debug)]
+pub enum LogError {
+    Io(String, std::io::Error),
+    Parse(String, usize, serde_json::Error),
+    Invalid(String, usize, String),
+}
+
+impl std::fmt::Display for LogError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogError::Io(path, e) => write!(f, "{}: {}", path, e)?,
+            LogError::Parse(path, line, e) => write!(f, "{}:{}: {}", path, line, e)?,
+            LogError::Invalid(path, line, message) => write!(f, "{}:{}: {}", path, line, message)?,
+        }
+        Ok(())
+    }
+}
+
+fn pose_array(pose: &Pose) -> [f64; 3] {
+    [pose.coord.x, pose.coord.y, pose.theta]
+}
+
+fn array_pose(pose: &[f64; 3]) -> Pose {
+    Pose {
+        coord: Coord {
+            x: pose[0],
+            y: pose[1],
+        },
+        theta: pose[2],
+    }
+}
+
+fn matrix_array<const N: usize>(m: &Matrix) -> [f64; N] {
+    let mut values = [0.0; N];
+    let cols = (N as f64).sqrt() as usize;
+    for (k, v) in values.iter_mut().enumerate() {
+        *v = m[(k / cols, k % cols)];
+    }
+    values
+}
+
+fn array_matrix<const N: usize>(values: &[f64; N]) -> Matrix {
+    let cols = (N as f64).sqrt() as usize;
+    let mut m = Matrix::new(cols, cols);
+    for (k, v) in values.iter().enumerate() {
+        m[(k / cols, k % cols)] = *v;
+    }
+    m
+}
+
+fn header(input: &Input, output: &Output) -> Header {
+    let agent = &output.agents[0];
+    Header {
+        version: VERSION,
+        agent_id: agent.id,
+        time_span: input.time_span,
+        time_interval: input.time_interval,
+        width: input.width,
+        height: input.height,
+        landmarks: input.landmarks.iter().map(|c| [c.x, c.y]).collect(),
+        init_pose: pose_array(&input.init_pose),
+        radius: input.radius,
+        nu: input.nu,
+        omega: input.omega,
+        estimators: output
+            .estimators
+            .iter()
+            .map(|estimator| EstimatorInfo {
+                name: estimator.name().to_string(),
+                radius: estimator.radius(),
+            })
+            .collect(),
+    }
+}
+
+fn steps(agent: &Agent) -> Vec<Step> {
+    agent
+        .pose_records
+        .iter()
+        .zip(agent.obs_records.iter())
+        .enumerate()
+        .map(|(turn, (pose, observation))| Step {
+            turn,
+            pose: pose_array(pose),
+            control: if turn == 0 {
+                None
+            } else {
+                let (nu, omega) = agent.control_records[turn];
+                Some([nu, omega])
+            },
+            observations: observation
+                .iter()
+                .map(|obs| ObservationRecord {
+                    id: if obs.id == Observation::UNKNOWN_ID {
+                        None
+                    } else {
+                        Some(obs.id)
+                    },
+                    dist: obs.dist,
+                    angle: obs.angle,
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+// particle_strideごとに1個のパーティクルを書き出す(0なら書き出さない)
+fn estimate(estimator: &dyn Filter, idx: usize, turn: usize, particle_stride: usize) -> Estimate {
+    let (mean, cov, particles) = match estimator.belief(turn) {
+        Belief::Particles { poses, mean, cov } => {
+            let particles = if particle_stride > 0 {
+                Some(
+                    poses
+                        .iter()
+                        .step_by(particle_stride)
+                        .map(pose_array)
+                        .collect(),
+                )
+            } else {
+                None
+            };
+            (mean, cov, particles)
+        }
+        Belief::Gaussian { mean, cov } => (mean, cov, None),
+    };
+    Estimate {
+        estimator: idx,
+        turn,
+        mean: pose_array(&mean),
+        cov: matrix_array(&cov),
+        particles,
+        particle_num: estimator.particle_num(turn),
+        ess: estimator.effective_sample_size(turn),
+        landmarks: estimator
+            .landmark_estimates(turn)
+            .iter()
+            .map(|l| LandmarkRecord {
+                id: l.id,
+                x: l.coord.x,
+                y: l.coord.y,
+                cov: matrix_array(&l.cov),
+            })
+            .collect(),
+        bias: estimator.bias_estimate(turn).map(|b| BiasRecord {
+            mean: b.mean,
+            std: b.std,
+        }),
+        reset: estimator.reset_event(turn).map(|event| match event {
+            ResetEvent::Random => ResetRecord::Random,
+            ResetEvent::Sensor => ResetRecord::Sensor,
+            ResetEvent::Expansion => ResetRecord::Expansion,
+            ResetEvent::Augmented { injected } => ResetRecord::Augmented { injected },
+        }),
+    }
+}
+
+fn reset_event(record: &ResetRecord) -> ResetEvent {
+    match *record {
+        ResetRecord::Random => ResetEvent::Random,
+        ResetRecord::Sensor => ResetEvent::Sensor,
+        ResetRecord::Expansion => ResetEvent::Expansion,
+        ResetRecord::Augmented { injected } => ResetEvent::Augmented { injected },
+    }
+}
+
+// 先頭のエージェントの走行と全推定器の推定をpathに書き出す
+pub fn export(
+    path: &str,
+    input: &Input,
+    output: &Output,
+    particle_stride: usize,
+) -> std::io::Result<()> {
+    if Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext == "jsonl")
+    {
+        write_jsonl(path, input, output, particle_stride)
+    } else {
+        write_csv_dir(path, input, output, particle_stride)
+    }
+}
+
+fn write_jsonl(
+    path: &str,
+    input: &Input,
+    output: &Output,
+    particle_stride: usize,
+) -> std::io::Result<()> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    write_record(&mut file, &Record::Header(header(input, output)))?;
+    for step in steps(&output.agents[0]) {
+        let turn = step.turn;
+        write_record(&mut file, &Record::Step(step))?;
+        for (idx, estimator) in output.estimators.iter().enumerate() {
+            let estimate = estimate(estimator.as_ref(), idx, turn, particle_stride);
+            write_record(&mut file, &Record::Estimate(estimate))?;
+        }
+    }
+    file.flush()
+}
+
+fn write_record<W: Write>(file: &mut W, record: &Record) -> std::io::Result<()> {
+    serde_json::to_writer(&mut *file, record)?;
+    writeln!(file)
+}
+
+fn create_csv(
+    dir: &Path,
+    name: &str,
+    columns: &str,
+) -> std::io::Result<std::io::BufWriter<std::fs::File>> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(dir.join(name))?);
+    writeln!(file, "{}", columns)?;
+    Ok(file)
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn write_csv_dir(
+    path: &str,
+    input: &Input,
+    output: &Output,
+    particle_stride: usize,
+) -> std::io::Result<()> {
+    let dir = Path::new(path);
+    std::fs::create_dir_all(dir)?;
+
+    let mut landmarks = create_csv(dir, "landmarks.csv", "id,x,y")?;
+    for (id, c) in input.landmarks.iter().enumerate() {
+        writeln!(landmarks, "{},{},{}", id, c.x, c.y)?;
+    }
+    landmarks.flush()?;
+
+    let steps = steps(&output.agents[0]);
+    let mut truth = create_csv(dir, "truth.csv", "turn,x,y,theta,nu,omega")?;
+    let mut observations = create_csv(dir, "observations.csv", "turn,id,dist,angle")?;
+    for step in steps.iter() {
+        let [x, y, theta] = step.pose;
+        writeln!(
+            truth,
+            "{},{},{},{},{},{}",
+            step.turn,
+            x,
+            y,
+            theta,
+            optional(step.control.map(|c| c[0])),
+            optional(step.control.map(|c| c[1]))
+        )?;
+        for obs in step.observations.iter() {
+            writeln!(
+                observations,
+                "{},{},{},{}",
+                step.turn,
+                optional(obs.id),
+                obs.dist,
+                obs.angle
+            )?;
+        }
+    }
+    truth.flush()?;
+    observations.flush()?;
+
+    let mut estimates = create_csv(
+        dir,
+        "estimates.csv",
+        "estimator,name,turn,x,y,theta,cov_xx,cov_xy,cov_xtheta,cov_yy,cov_ytheta,cov_thetatheta,particle_num,ess,reset",
+    )?;
+    let mut particles = create_csv(dir, "particles.csv", "estimator,name,turn,x,y,theta")?;
+    let mut landmark_estimates = create_csv(
+        dir,
+        "landmark_estimates.csv",
+        "estimator,name,turn,id,x,y,cov_xx,cov_xy,cov_yy",
+    )?;
+    for (idx, estimator) in output.estimators.iter().enumerate() {
+        let name = estimator.name();
+        for step in steps.iter() {
+            let e = estimate(estimator.as_ref(), idx, step.turn, particle_stride);
+            let c = e.cov;
+            writeln!(
+                estimates,
+                "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                idx,
+                name,
+                e.turn,
+                e.mean[0],
+                e.mean[1],
+                e.mean[2],
+                c[0],
+                c[1],
+                c[2],
+                c[4],
+                c[5],
+                c[8],
+                optional(e.particle_num),
+                optional(e.ess),
+                optional(e.reset.as_ref().map(reset_event))
+            )?;
+            for p in e.particles.iter().flatten() {
+                writeln!(
+                    particles,
+                    "{},{},{},{},{},{}",
+                    idx, name, e.turn, p[0], p[1], p[2]
+                )?;
+            }
+            for l in e.landmarks.iter() {
+                writeln!(
+                    landmark_estimates,
+                    "{},{},{},{},{},{},{},{},{}",
+                    idx, name, e.turn, l.id, l.x, l.y, l.cov[0], l.cov[1], l.cov[3]
+                )?;
+            }
+        }
+    }
+    estimates.flush()?;
+    particles.flush()?;
+    landmark_estimates.flush()
+}
+
+// 書き出したJSON Linesのログを読み込み, ビジュアライザに渡せる形に組み立てる
+pub fn load(path: &str) -> Result<(Input, Output), LogError> {
+    let file = std::fs::File::open(path).map_err(|e| LogError::Io(path.to_string(), e))?;
+    let invalid =
+        |line: usize, message: &str| LogError::Invalid(path.to_string(), line, message.to_string());
+
+    let mut header: Option<Header> = None;
+    let mut steps: Vec<Step> = vec![];
+    let mut estimators: Vec<ReplayedEstimator> = vec![];
+    for (idx, line) in std::io::BufReader::new(file).lines().enumerate() {
+        let line_no = idx + 1;
+        let line = line.map_err(|e| LogError::Io(path.to_string(), e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Record = serde_json::from_str(&line)
+            .map_err(|e| LogError::Parse(path.to_string(), line_no, e))?;
+        match (record, &header) {
+            (Record::Header(h), None) => {
+                if h.version != VERSION {
+                    return Err(invalid(
+                        line_no,
+                        &format!("unsupported log version {}", h.version),
+                    ));
+                }
+                estimators = h
+                    .estimators
+                    .iter()
+                    .map(|info| ReplayedEstimator {
+                        name: info.name.clone(),
+                        radius: info.radius,
+                        records: vec![],
+                    })
+                    .collect();
+                header = Some(h);
+            }
+            (Record::Header(_), Some(_)) => return Err(invalid(line_no, "duplicate header")),
+            (_, None) => return Err(invalid(line_no, "the first record must be a header")),
+            (Record::Step(step), Some(_)) => {
+                if step.turn != steps.len() {
+                    return Err(invalid(
+                        line_no,
+                        &format!("expected turn {}, got {}", steps.len(), step.turn),
+                    ));
+                }
+                steps.push(step);
+            }
+            (Record::Estimate(e), Some(_)) => {
+                let estimator = estimators.get_mut(e.estimator).ok_or_else(|| {
+                    invalid(line_no, &format!("unknown estimator {}", e.estimator))
+                })?;
+                if e.turn != estimator.records.len() || e.turn >= steps.len() {
+                    return Err(invalid(
+                        line_no,
+                        &format!("unexpected turn {} for estimator {}", e.turn, e.estimator),
+                    ));
+                }
+                estimator.records.push(e);
+            }
+        }
+    }
+
+    let header = header.ok_or_else(|| invalid(0, "empty log"))?;
+    if steps.is_empty() {
+        return Err(invalid(0, "no steps recorded"));
+    }
+    if let Some(e) = estimators.iter().find(|e| e.records.len() != steps.len()) {
+        return Err(invalid(
+            0,
+            &format!("estimator `{}` is missing some turns", e.name),
+        ));
+    }
+
+    let init_pose = array_pose(&header.init_pose);
+    let input = Input {
+        time_span: header.time_span,
+        time_interval: header.time_interval,
+        height: header.height,
+        width: header.width,
+        landmarks: header
+            .landmarks
+            .iter()
+            .map(|&[x, y]| Coord { x, y })
+            .collect(),
+        init_pose,
+        radius: header.radius,
+        nu: header.nu,
+        omega: header.omega,
+    };
+    let mut agent = Agent::new(
+        header.agent_id,
+        header.time_interval,
+        init_pose,
+        header.radius,
+    );
+    agent.pose_records = steps.iter().map(|s| array_pose(&s.pose)).collect();
+    agent.control_records = steps
+        .iter()
+        .map(|s| s.control.map_or((0.0, 0.0), |[nu, omega]| (nu, omega)))
+        .collect();
+    agent.obs_records = steps
+        .iter()
+        .map(|s| {
+            s.observations
+                .iter()
+                .map(|obs| Observation {
+                    id: obs.id.unwrap_or(Observation::UNKNOWN_ID),
+                    dist: obs.dist,
+                    angle: obs.angle,
+                })
+                .collect()
+        })
+        .collect();
+    agent.pose = *agent.pose_records.last().unwrap();
+    let output = Output {
+        agents: vec![agent],
+        estimators: estimators
+            .into_iter()
+            .map(|e| Box::new(e) as Box<dyn Filter>)
+            .collect(),
+    };
+    Ok((input, output))
+}
+
+// ログに記録された推定を再生するだけの推定器
+// 記録済みのturnしか持たないので, 動作更新や観測更新では何もしない
+#[derive(Debug)]
+pub struct ReplayedEstimator {
+    name: String,
+    radius: f64,
+    records: Vec<Estimate>,
+}
+
+impl Filter for ReplayedEstimator {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn predict(&mut self, _nu: f64, _omega: f64) {}
+    fn update(&mut self, _observation: &[Observation], _landmarks: &[Coord]) {}
+    fn finalize(&mut self) {}
+    fn pose(&self) -> Pose {
+        self.estimate(self.records.len() - 1)
+    }
+    fn estimate(&self, turn: usize) -> Pose {
+        array_pose(&self.records[turn].mean)
+    }
+    fn belief(&self, turn: usize) -> Belief {
+        let record = &self.records[turn];
+        let mean = array_pose(&record.mean);
+        let cov = array_matrix(&record.cov);
+        match &record.particles {
+            Some(particles) => Belief::Particles {
+                poses: particles.iter().map(array_pose).collect(),
+                mean,
+                cov,
+            },
+            None => Belief::Gaussian { mean, cov },
+        }
+    }
+    fn particle_num(&self, turn: usize) -> Option<usize> {
+        self.records[turn].particle_num
+    }
+    fn effective_sample_size(&self, turn: usize) -> Option<f64> {
+        self.records[turn].ess
+    }
+    fn landmark_estimates(&self, turn: usize) -> Vec<LandmarkEstimate> {
+        self.records[turn]
+            .landmarks
+            .iter()
+            .map(|l| LandmarkEstimate {
+                id: l.id,
+                coord: Coord { x: l.x, y: l.y },
+                cov: array_matrix(&l.cov),
+            })
+            .collect()
+    }
+    fn bias_estimate(&self, turn: usize) -> Option<BiasEstimate> {
+        self.records[turn].bias.as_ref().map(|b| BiasEstimate {
+            mean: b.mean,
+            std: b.std,
+        })
+    }
+    fn reset_event(&self, turn: usize) -> Option<ResetEvent> {
+        self.records[turn].reset.as_ref().map(reset_event)
+    }
+}
@@ -1,109 +1,202 @@
 #![allow(dead_code)]
 
 mod agent;
+mod association;
+mod batch;
+mod bias_ekf;
+mod calibration;
 mod camera;
 mod common;
+mod control;
+mod ekf;
+mod ekf_slam;
 mod estimator;
+mod fast_slam;
+mod graph_slam;
+mod likelihood;
+mod log;
+mod matrix;
+mod metrics;
 mod motion;
 mod normal;
+mod planner;
+mod reset;
+mod scenario;
+mod sparse;
+mod ukf;
 mod vis;
+mod world;
 
-use agent::{Agent, Pose};
+use agent::Agent;
 use common::{convert_radian_in_range, Coord};
-use estimator::{Estimator, MotionNoisePdf};
-use std::f64::consts::PI;
+use scenario::{CameraIdsConfig, Scenario};
+use world::{Input, Output};
+
+const USAGE: &str =
+    "usage: a [SCENARIO.toml] [--batch RUNS] [--divergence-threshold METERS] [--graph-slam]\n         [--calibrate ROBOTS] [--metrics CSV]\n         [--export LOG.jsonl|DIR] [--particle-stride N] [--live]";
 
 fn main() {
-    let input = Input {
-        time_span: 30.0,   // sec
-        time_interval: 0.1, // sec
-        height: 10,
-        width: 10,
-        landmarks: vec![
-            Coord { x: -4.0, y: 2.0 },
-            Coord { x: 2.0, y: -3.0 },
-            Coord { x: 3.0, y: 3.0 },
-        ],
-        // ロボット初期姿勢
-        init_pose: Pose {
-            coord: Coord { x: 0.0, y: 0.0 },
-            theta: 0.0,
-        },
-        radius: 0.2,                  // ロボット半径, m
-        nu: 0.2,                      // ロボットの前方方向の速度, m/s
-        omega: 10.0_f64.to_radians(), // ロボットの中心の角速度, rad/s
+    let mut scenario_path = None;
+    let mut batch_runs = None;
+    let mut divergence_threshold = 1.0;
+    let mut graph_slam = false;
+    let mut calibrate_robots = None;
+    let mut metrics_path: Option<String> = None;
+    let mut export_path: Option<String> = None;
+    let mut particle_stride = 1;
+    let mut live = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--batch" => batch_runs = Some(parse_arg(&arg, args.next())),
+            "--divergence-threshold" => divergence_threshold = parse_arg(&arg, args.next()),
+            "--graph-slam" => graph_slam = true,
+            "--calibrate" => calibrate_robots = Some(parse_arg(&arg, args.next())),
+            "--metrics" => metrics_path = Some(parse_arg(&arg, args.next())),
+            "--export" => export_path = Some(parse_arg(&arg, args.next())),
+            "--particle-stride" => particle_stride = parse_arg(&arg, args.next()),
+            "--live" => live = true,
+            _ if arg.starts_with("--") => exit_with(&format!("unknown option `{}`", arg)),
+            _ => scenario_path = Some(arg),
+        }
+    }
+
+    // 引数でシナリオファイルを指定しなければ既定のシナリオで実行する
+    let scenario = match scenario_path {
+        Some(path) => Scenario::load(&path).unwrap_or_else(|e| {
+            exit_with(&format!("invalid scenario: {}", e));
+        }),
+        None => Scenario::default(),
     };
 
-    let mut agent = Agent::new(
-        0, // 乱数シード
-        input.time_interval,
-        input.init_pose,
-        input.radius,
-        input.nu,
-        input.omega,
-    );
-
-    // 動作ノイズ(実際は未知のパラメータ)
-    let noise_per_meter = 5.0; // 道のりあたりに踏みつける小石の期待値
-    let noise_std = PI / 60.0; // 小石を踏んだ時にずれる角度の確率密度関数(正規分布)
-    agent.set_motion_noise(noise_per_meter, noise_std);
-
-    // 観測ノイズ(実際は未知のパラメータ)
-    let distance_noise_rate = 0.1; // 単位観測長当たりの観測距離ノイズの標準偏差
-    let direction_noise = PI / 90.0; // 観測角度ノイズの標準偏差
-    agent.set_camera_noise(distance_noise_rate, direction_noise);
-
-    let particle_num = 100;
-
-    // 実験により得た各ノイズの標準偏差
-    let nn_std = 0.19;
-    let no_std = 0.001;
-    let on_std = 0.13;
-    let oo_std = 0.2;
-    let distance_rate_std = 0.14;
-    let direction_std = 0.05;
-    let motion_noise_pdf = MotionNoisePdf::new(nn_std, no_std, on_std, oo_std);
-
-    let mut estimator = Estimator::new(
-        input.time_interval,
-        input.init_pose,
-        input.radius,
-        input.nu,
-        input.omega,
-        particle_num,
-        motion_noise_pdf,
-        distance_rate_std,
-        direction_std,
-    );
-
-    let max_turn = (input.time_span / input.time_interval) as usize;
-    for _ in 0..max_turn {
-        let observation = agent.action(&input.landmarks);
-        estimator.decision(&observation, &input.landmarks);
+    // ビジュアライザを使わずに複数シードで試行し統計を出力する
+    if let Some(runs) = batch_runs {
+        batch::run(&scenario, runs, divergence_threshold);
+        return;
+    }
+    // 走行後に記録全体をグラフベースSLAMで最適化し, オンラインの推定器と比べる
+    if graph_slam {
+        if scenario.agent.camera_ids != CameraIdsConfig::Known {
+            exit_with("`--graph-slam` needs agent.camera_ids = \"known\"");
+        }
+        graph_slam::run(&scenario);
+        return;
     }
 
-    let output = Output {
-        agents: vec![agent],
-        estimator,
-    };
+    // 雑音の標準偏差を直進・旋回・観測の試行から推定し, [estimator]の形式で出力する
+    if let Some(robots) = calibrate_robots {
+        if robots == 0 {
+            exit_with("`--calibrate` needs at least 1 robot");
+        }
+        if scenario.robot.nu == 0.0 || scenario.robot.omega == 0.0 {
+            exit_with("`--calibrate` needs nonzero robot.nu and robot.omega");
+        }
+        calibration::run(&scenario, robots);
+        return;
+    }
+
+    // 再生に合わせてシミュレーションを進め, 雑音やパーティクル数を走行中に変えられるようにする
+    if live {
+        if !cfg!(feature = "local") {
+            exit_with("`--live` needs the `local` feature");
+        }
+        let defaults = live_settings(&scenario);
+        let plan = scenario.plan();
+        let build: vis::LiveBuild = Box::new(move |seed_offset| {
+            let (input, output) = start(&scenario, seed_offset);
+            let policy = scenario.build_control(&input);
+            (input, output, policy)
+        });
+        #[cfg(feature = "local")]
+        vis::live_visualizer(vis::Live::new(build, defaults), plan);
+        #[cfg(not(feature = "local"))]
+        let _ = (build, defaults, plan);
+        return;
+    }
+
+    let (input, output) = simulate(&scenario, 0);
+    let max_turn = input.max_turn();
+
+    // 真の姿勢・観測・推定をJSON Lines(replayで再生できる)かCSVのディレクトリに書き出す
+    if let Some(path) = &export_path {
+        if let Err(e) = log::export(path, &input, &output, particle_stride) {
+            exit_with(&format!("cannot write `{}`: {}", path, e));
+        }
+    }
+
+    // 真の姿勢に対する誤差とNEESを要約して表示し, ステップごとの値をCSVに書き出す
+    if let Some(path) = metrics_path {
+        let truth = &output.agents[0].pose_records;
+        let metrics: Vec<_> = output
+            .estimators
+            .iter()
+            .map(|estimator| metrics::evaluate(estimator.as_ref(), truth, max_turn))
+            .collect();
+        metrics::print_summary(&metrics);
+        if let Some(path) = scenario.reference_path(&input) {
+            let tracking = metrics::evaluate_tracking(&path, truth, max_turn);
+            let controller = scenario
+                .control
+                .is_closed_loop()
+                .then(|| output.estimators[0].name());
+            metrics::print_tracking(&tracking, controller);
+        }
+        if let Err(e) = metrics::write_csv(&path, &metrics) {
+            exit_with(&format!("cannot write `{}`: {}", path, e));
+        }
+        return;
+    }
 
     #[cfg(feature = "local")]
-    vis::visualizer(input, output, max_turn);
+    vis::visualizer(input, output, max_turn, scenario.plan());
+    #[cfg(not(feature = "local"))]
+    let _ = (output, max_turn);
+}
+
+fn parse_arg<T: std::str::FromStr>(name: &str, value: Option<String>) -> T {
+    match value.as_deref().map(str::parse) {
+        Some(Ok(v)) => v,
+        _ => exit_with(&format!("`{}` needs a valid value", name)),
+    }
 }
 
-pub struct Input {
-    time_span: f64,
-    time_interval: f64,
-    height: usize,
-    width: usize,
-    landmarks: Vec<Coord>,
-    init_pose: Pose,
-    radius: f64,
-    nu: f64,
-    omega: f64,
+fn exit_with(message: &str) -> ! {
+    eprintln!("{}\n{}", message, USAGE);
+    std::process::exit(1);
+}
+
+// ライブモードのウィジェットの初期値(シナリオのエージェントの雑音とMCLのパーティクル数)
+fn live_settings(scenario: &Scenario) -> vis::LiveSettings {
+    let agent = &scenario.agent;
+    let motion = agent.motion_noise.as_ref();
+    let camera = agent.camera_noise.as_ref();
+    vis::LiveSettings {
+        noise_per_meter: motion.map_or(0.0, |c| c.noise_per_meter),
+        noise_std: motion.map_or(0.0, |c| c.noise_std),
+        distance_noise_rate: camera.map_or(0.0, |c| c.distance_noise_rate),
+        direction_noise: camera.map_or(0.0, |c| c.direction_noise),
+        particle_num: scenario.estimator.particle_num,
+    }
 }
 
-pub struct Output {
-    agents: Vec<Agent>,
-    estimator: Estimator,
+// シナリオから走行開始時のエージェントと推定器を作る
+pub fn start(scenario: &Scenario, seed_offset: u64) -> (Input, Output) {
+    let input = scenario.input();
+    let agent = scenario.build_agent(&input, seed_offset);
+    let estimators = scenario.build_estimators(&input, seed_offset);
+    let output = Output {
+        agents: vec![agent],
+        estimators,
+    };
+    (input, output)
+}
+
+pub fn simulate(scenario: &Scenario, seed_offset: u64) -> (Input, Output) {
+    let (input, mut output) = start(scenario, seed_offset);
+    let mut policy = scenario.build_control(&input);
+    for turn in 1..=input.max_turn() {
+        let (nu, omega) = policy.control(turn, &output.control_estimate());
+        output.step(&input.landmarks, nu, omega);
+    }
+    (input, output)
 }
@@ -0,0 +1,279 @@
+use std::ops::{Add, Index, IndexMut, Mul, Sub};
+
+// 行優先で要素を保持する密行列
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix {
+    pub rows: usize,
+    pub cols: usize,
+    pub data: Vec<f64>,
+}
+
+impl Matrix {
+    pub fn new(rows: usize, cols: usize) -> Self {
+        Self {
+            rows,
+            cols,
+            data: vec![0.0; rows * cols],
+        }
+    }
+    pub fn from_vec(rows: usize, cols: usize, data: Vec<f64>) -> Self {
+        assert!(rows * cols == data.len());
+        Self { rows, cols, data }
+    }
+    pub fn identity(n: usize) -> Self {
+        let mut m = Self::new(n, n);
+        for i in 0..n {
+            m[(i, i)] = 1.0;
+        }
+        m
+    }
+    pub fn diag(values: &[f64]) -> Self {
+        let mut m = Self::new(values.len(), values.len());
+        for (i, &v) in values.iter().enumerate() {
+            m[(i, i)] = v;
+        }
+        m
+    }
+    pub fn column(values: &[f64]) -> Self {
+        Self::from_vec(values.len(), 1, values.to_vec())
+    }
+    pub fn transpose(&self) -> Self {
+        let mut m = Self::new(self.cols, self.rows);
+        for i in 0..self.rows {
+            for j in 0..self.cols {
+                m[(j, i)] = self[(i, j)];
+            }
+        }
+        m
+    }
+    pub fn scale(&self, k: f64) -> Self {
+        Self::from_vec(
+            self.rows,
+            self.cols,
+            self.data.iter().map(|x| x * k).collect(),
+        )
+    }
+    // 対称性が数値誤差で崩れないように(A + A^T) / 2 とする
+    pub fn symmetrize(&self) -> Self {
+        (self + &self.transpose()).scale(0.5)
+    }
+    // 部分ピボット付きガウス・ジョルダン法による逆行列
+    pub fn inverse(&self) -> Option<Self> {
+        assert!(self.rows == self.cols);
+        let n = self.rows;
+        let mut a = self.clone();
+        let mut inv = Self::identity(n);
+        for col in 0..n {
+            let pivot = (col..n)
+                .max_by(|&i, &j| a[(i, col)].abs().total_cmp(&a[(j, col)].abs()))
+                .unwrap();
+            if a[(pivot, col)].abs() < 1e-300 {
+                return None;
+            }
+            a.swap_rows(col, pivot);
+            inv.swap_rows(col, pivot);
+            let p = a[(col, col)];
+            for j in 0..n {
+                a[(col, j)] /= p;
+                inv[(col, j)] /= p;
+            }
+            for i in 0..n {
+                if i == col {
+                    continue;
+                }
+                let f = a[(i, col)];
+                if f == 0.0 {
+                    continue;
+                }
+                for j in 0..n {
+                    a[(i, j)] -= f * a[(col, j)];
+                    inv[(i, j)] -= f * inv[(col, j)];
+                }
+            }
+        }
+        Some(inv)
+    }
+    // 下三角行列Lを返す(A = L L^T)
+    pub fn cholesky(&self) -> Option<Self> {
+        assert!(self.rows == self.cols);
+        let n = self.rows;
+        let mut l = Self::new(n, n);
+        for i in 0..n {
+            for j in 0..=i {
+                let mut s = self[(i, j)];
+                for k in 0..j {
+                    s -= l[(i, k)] * l[(j, k)];
+                }
+                if i == j {
+                    if s <= 0.0 {
+                        return None;
+                    }
+                    l[(i, j)] = s.sqrt();
+                } else {
+                    l[(i, j)] = s / l[(j, j)];
+                }
+            }
+        }
+        Some(l)
+    }
+    pub fn determinant(&self) -> f64 {
+        assert!(self.rows == self.cols);
+        let n = self.rows;
+        let mut a = self.clone();
+        let mut det = 1.0;
+        for col in 0..n {
+            let pivot = (col..n)
+                .max_by(|&i, &j| a[(i, col)].abs().total_cmp(&a[(j, col)].abs()))
+                .unwrap();
+            if a[(pivot, col)] == 0.0 {
+                return 0.0;
+            }
+            if pivot != col {
+                a.swap_rows(col, pivot);
+                det = -det;
+            }
+            det *= a[(col, col)];
+            for i in col + 1..n {
+                let f = a[(i, col)] / a[(col, col)];
+                for j in col..n {
+                    a[(i, j)] -= f * a[(col, j)];
+                }
+            }
+        }
+        det
+    }
+    // 部分行列の取り出し
+    pub fn block(&self, row: usize, col: usize, rows: usize, cols: usize) -> Self {
+        let mut m = Self::new(rows, cols);
+        for i in 0..rows {
+            for j in 0..cols {
+                m[(i, j)] = self[(row + i, col + j)];
+            }
+        }
+        m
+    }
+    pub fn set_block(&mut self, row: usize, col: usize, m: &Matrix) {
+        for i in 0..m.rows {
+            for j in 0..m.cols {
+                self[(row + i, col + j)] = m[(i, j)];
+            }
+        }
+    }
+    fn swap_rows(&mut self, i: usize, j: usize) {
+        if i == j {
+            return;
+        }
+        for k in 0..self.cols {
+            self.data.swap(i * self.cols + k, j * self.cols + k);
+        }
+    }
+}
+
+impl Index<(usize, usize)> for Matrix {
+    type Output = f64;
+    fn index(&self, (i, j): (usize, usize)) -> &Self::Output {
+        &self.data[i * self.cols + j]
+    }
+}
+
+impl IndexMut<(usize, usize)> for Matrix {
+    fn index_mut(&mut self, (i, j): (usize, usize)) -> &mut Self::Output {
+        &mut self.data[i * self.cols + j]
+    }
+}
+
+impl Add<&Matrix> for &Matrix {
+    type Output = Matrix;
+    fn add(self, rhs: &Matrix) -> Self::Output {
+        assert!(self.rows == rhs.rows && self.cols == rhs.cols);
+        Matrix::from_vec(
+            self.rows,
+            self.cols,
+            self.data
+                .iter()
+                .zip(rhs.data.iter())
+                .map(|(a, b)| a + b)
+                .collect(),
+        )
+    }
+}
+
+impl Sub<&Matrix> for &Matrix {
+    type Output = Matrix;
+    fn sub(self, rhs: &Matrix) -> Self::Output {
+        assert!(self.rows == rhs.rows && self.cols == rhs.cols);
+        Matrix::from_vec(
+            self.rows,
+            self.cols,
+            self.data
+                .iter()
+                .zip(rhs.data.iter())
+                .map(|(a, b)| a - b)
+                .collect(),
+        )
+    }
+}
+
+impl Mul<&Matrix> for &Matrix {
+    type Output = Matrix;
+    fn mul(self, rhs: &Matrix) -> Self::Output {
+        assert!(self.cols == rhs.rows);
+        let mut m = Matrix::new(self.rows, rhs.cols);
+        for i in 0..self.rows {
+            for k in 0..self.cols {
+                let a = self[(i, k)];
+                if a == 0.0 {
+                    continue;
+                }
+                for j in 0..rhs.cols {
+                    m[(i, j)] += a * rhs[(k, j)];
+                }
+            }
+        }
+        m
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &Matrix, b: &Matrix, tol: f64) {
+        assert_eq!((a.rows, a.cols), (b.rows, b.cols));
+        for (x, y) in a.data.iter().zip(b.data.iter()) {
+            assert!((x - y).abs() < tol, "{:?} != {:?}", a, b);
+        }
+    }
+
+    // 対称正定値な3x3行列
+    fn spd() -> Matrix {
+        Matrix::from_vec(3, 3, vec![4.0, 1.0, 0.5, 1.0, 3.0, -0.2, 0.5, -0.2, 2.0])
+    }
+
+    #[test]
+    fn inverse_gives_identity() {
+        let a = spd();
+        let inv = a.inverse().unwrap();
+        assert_close(&(&a * &inv), &Matrix::identity(3), 1e-12);
+        assert_close(&(&inv * &a), &Matrix::identity(3), 1e-12);
+    }
+
+    #[test]
+    fn inverse_of_singular_matrix_is_none() {
+        let a = Matrix::from_vec(2, 2, vec![1.0, 2.0, 2.0, 4.0]);
+        assert!(a.inverse().is_none());
+    }
+
+    #[test]
+    fn cholesky_reconstructs_matrix() {
+        let a = spd();
+        let l = a.cholesky().unwrap();
+        assert_eq!([l[(0, 1)], l[(0, 2)], l[(1, 2)]], [0.0; 3]);
+        assert_close(&(&l * &l.transpose()), &a, 1e-12);
+    }
+
+    #[test]
+    fn cholesky_of_indefinite_matrix_is_none() {
+        assert!(Matrix::diag(&[1.0, -1.0]).cholesky().is_none());
+    }
+}
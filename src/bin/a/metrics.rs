@@ -0,0 +1,260 @@
+use std::io::Write;
+
+use crate::agent::Pose;
+use crate::common::{convert_radian_in_range, Coord};
+use crate::control::closest_on_segment;
+use crate::estimator::{Belief, Filter};
+use crate::matrix::Matrix;
+use crate::normal::chi_squared_quantile;
+
+// NEESの整合性を判定する信頼度(両側)
+const CONSISTENCY_CONFIDENCE: f64 = 0.95;
+// 姿勢の次元(NEESが従うカイ二乗分布の自由度)
+const POSE_DIM: usize = 3;
+// 標準偏差(m, rad)がこれより小さい軸がある共分散は潰れているとみなしNEESを求めない
+const MIN_STD: f64 = 1e-6;
+// 相関行列の行列式がこれより小さい共分散も, ほぼ特異とみなしNEESを求めない
+const MIN_CORRELATION_DET: f64 = 1e-6;
+
+// 1ステップ分の真の姿勢に対する推定誤差
+#[derive(Debug, Clone, Copy)]
+pub struct StepMetrics {
+    pub turn: usize,
+    pub position_error: f64,
+    pub heading_error: f64, // [-π, π)に収めた向きの誤差
+    pub nees: Option<f64>,  // 共分散が特異かほぼ特異(パーティクルが潰れたときなど)なステップはNone
+}
+
+// 1試行分の誤差の要約
+// NEESは推定器の共分散が実際の誤差に見合っていれば平均が姿勢の次元(3)になり,
+// 大きければ過信(共分散が小さすぎる), 小さければ過小評価(共分散が大きすぎる)を表す
+#[derive(Debug, Clone, Copy)]
+pub struct MetricsSummary {
+    pub position_rmse: f64,
+    pub heading_rmse: f64,
+    pub max_position_error: f64,
+    pub final_position_error: f64,
+    pub mean_nees: Option<f64>,
+    pub nees_bounds: (f64, f64),          // 平均NEESの信頼区間
+    pub nees_in_bounds_rate: Option<f64>, // 各ステップのNEESが信頼区間内に入った割合
+}
+
+impl MetricsSummary {
+    // 平均NEESから判定した共分散の整合性
+    pub fn consistency(&self) -> &'static str {
+        match self.mean_nees {
+            Some(nees) if nees > self.nees_bounds.1 => "overconfident",
+            Some(nees) if nees < self.nees_bounds.0 => "underconfident",
+            Some(_) => "consistent",
+            None => "-",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RunMetrics {
+    pub name: String,
+    pub steps: Vec<StepMetrics>,
+    pub summary: MetricsSummary,
+}
+
+// 推定器の各ステップの信念を真の姿勢(Agent::pose_records)と比べる
+// t=0は初期姿勢を与えているので除く
+pub fn evaluate(estimator: &dyn Filter, truth: &[Pose], max_turn: usize) -> RunMetrics {
+    let steps: Vec<StepMetrics> = truth
+        .iter()
+        .enumerate()
+        .take(max_turn + 1)
+        .skip(1)
+        .map(|(turn, actual)| {
+            let (estimate, cov) = match estimator.belief(turn) {
+                Belief::Particles { mean, cov, .. } => (mean, cov),
+                Belief::Gaussian { mean, cov } => (mean, cov),
+            };
+            let error = [
+                estimate.coord.x - actual.coord.x,
+                estimate.coord.y - actual.coord.y,
+                convert_radian_in_range(estimate.theta - actual.theta),
+            ];
+            StepMetrics {
+                turn,
+                position_error: (error[0].powf(2.0) + error[1].powf(2.0)).sqrt(),
+                heading_error: error[2],
+                nees: nees(&error, &cov),
+            }
+        })
+        .collect();
+    RunMetrics {
+        name: estimator.name().to_string(),
+        summary: summarize(&steps),
+        steps,
+    }
+}
+
+// 経路を追従する制御での, 真の軌跡の経路(折れ線)からのずれの要約
+#[derive(Debug, Clone, Copy)]
+pub struct TrackingSummary {
+    pub cross_track_rmse: f64,
+    pub max_cross_track_error: f64,
+    pub goal_error: f64, // 最終ステップの真の位置と経路の終点の距離
+}
+
+// 真の姿勢(Agent::pose_records)を経路と比べる
+pub fn evaluate_tracking(path: &[Coord], truth: &[Pose], max_turn: usize) -> TrackingSummary {
+    let errors: Vec<f64> = truth
+        .iter()
+        .take(max_turn + 1)
+        .skip(1)
+        .map(|actual| cross_track_error(path, &actual.coord))
+        .collect();
+    let n = errors.len().max(1) as f64;
+    let goal = path.last().unwrap();
+    let last = &truth[max_turn.min(truth.len() - 1)].coord;
+    TrackingSummary {
+        cross_track_rmse: (errors.iter().map(|e| e.powf(2.0)).sum::<f64>() / n).sqrt(),
+        max_cross_track_error: errors.iter().copied().fold(0.0, f64::max),
+        goal_error: ((last.x - goal.x).powf(2.0) + (last.y - goal.y).powf(2.0)).sqrt(),
+    }
+}
+
+// 点pから折れ線の経路までの距離
+fn cross_track_error(path: &[Coord], p: &Coord) -> f64 {
+    if path.len() == 1 {
+        return closest_on_segment(&path[0], &path[0], p).0;
+    }
+    path.windows(2)
+        .map(|w| closest_on_segment(&w[0], &w[1], p).0)
+        .fold(f64::INFINITY, f64::min)
+}
+
+// 正規化推定誤差2乗 e^T P^-1 e
+// ほぼ特異な共分散は逆行列が求まっても桁外れの値になるので除く
+fn nees(error: &[f64; 3], cov: &Matrix) -> Option<f64> {
+    if !is_well_conditioned(cov) {
+        return None;
+    }
+    let e = Matrix::column(error);
+    let nees = (&(&e.transpose() * &cov.inverse()?) * &e)[(0, 0)];
+    if nees.is_finite() && nees >= 0.0 {
+        Some(nees)
+    } else {
+        None
+    }
+}
+
+// 各軸の標準偏差と, 単位によらない相関行列の行列式で共分散の条件を判定する
+fn is_well_conditioned(cov: &Matrix) -> bool {
+    let vars: Vec<f64> = (0..POSE_DIM).map(|i| cov[(i, i)]).collect();
+    if !vars.iter().all(|&v| v.is_finite() && v.sqrt() >= MIN_STD) {
+        return false;
+    }
+    let correlation_det = cov.determinant() / vars.iter().product::<f64>();
+    correlation_det.is_finite() && correlation_det >= MIN_CORRELATION_DET
+}
+
+fn summarize(steps: &[StepMetrics]) -> MetricsSummary {
+    let n = steps.len().max(1) as f64;
+    let neeses: Vec<f64> = steps.iter().filter_map(|s| s.nees).collect();
+    let lower = (1.0 - CONSISTENCY_CONFIDENCE) / 2.0;
+    let upper = 1.0 - lower;
+    // k個のNEESの和は自由度3kのカイ二乗分布に従う
+    let k = neeses.len().max(1);
+    let nees_bounds = (
+        chi_squared_quantile(lower, POSE_DIM * k) / k as f64,
+        chi_squared_quantile(upper, POSE_DIM * k) / k as f64,
+    );
+    let step_bounds = (
+        chi_squared_quantile(lower, POSE_DIM),
+        chi_squared_quantile(upper, POSE_DIM),
+    );
+    let (mean_nees, nees_in_bounds_rate) = if neeses.is_empty() {
+        (None, None)
+    } else {
+        let inside = neeses
+            .iter()
+            .filter(|&&e| step_bounds.0 <= e && e <= step_bounds.1)
+            .count();
+        (
+            Some(neeses.iter().sum::<f64>() / neeses.len() as f64),
+            Some(inside as f64 / neeses.len() as f64),
+        )
+    };
+    MetricsSummary {
+        position_rmse: (steps
+            .iter()
+            .map(|s| s.position_error.powf(2.0))
+            .sum::<f64>()
+            / n)
+            .sqrt(),
+        heading_rmse: (steps.iter().map(|s| s.heading_error.powf(2.0)).sum::<f64>() / n).sqrt(),
+        max_position_error: steps.iter().map(|s| s.position_error).fold(0.0, f64::max),
+        final_position_error: steps.last().map(|s| s.position_error).unwrap_or(0.0),
+        mean_nees,
+        nees_bounds,
+        nees_in_bounds_rate,
+    }
+}
+
+pub fn print_summary(metrics: &[RunMetrics]) {
+    println!(
+        "{:<10} {:>10} {:>12} {:>10} {:>10} {:>18} {:>10} {:>15}",
+        "", "pos [m]", "head [deg]", "max [m]", "mean NEES", "95% bounds", "in 95%", "covariance"
+    );
+    for m in metrics.iter() {
+        let s = &m.summary;
+        println!(
+            "{:<10} {:>10.3} {:>12.2} {:>10.3} {:>10} {:>8.2} - {:>7.2} {:>10} {:>15}",
+            m.name,
+            s.position_rmse,
+            s.heading_rmse.to_degrees(),
+            s.max_position_error,
+            s.mean_nees
+                .map(|e| format!("{:.2}", e))
+                .unwrap_or_else(|| "-".to_string()),
+            s.nees_bounds.0,
+            s.nees_bounds.1,
+            s.nees_in_bounds_rate
+                .map(|r| format!("{:.1}%", 100.0 * r))
+                .unwrap_or_else(|| "-".to_string()),
+            s.consistency()
+        );
+    }
+}
+
+// controllerは制御に推定姿勢を使った推定器の名前(予定表どおりに走る方策ならNone)
+pub fn print_tracking(tracking: &TrackingSummary, controller: Option<&str>) {
+    println!(
+        "path tracking{}: cross-track RMSE {:.3} m, max {:.3} m, goal error {:.3} m",
+        controller
+            .map(|name| format!(" (steered by {})", name))
+            .unwrap_or_default(),
+        tracking.cross_track_rmse,
+        tracking.max_cross_track_error,
+        tracking.goal_error
+    );
+}
+
+// ステップごとの誤差をCSVに書き出す(NEESが求まらないステップは空欄)
+// estimatorは推定器の添字(estimator.kindsの順)で, 同じ種類の推定器が複数あっても区別できる
+pub fn write_csv(path: &str, metrics: &[RunMetrics]) -> std::io::Result<()> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(
+        file,
+        "estimator,name,turn,position_error,heading_error,nees"
+    )?;
+    for (idx, m) in metrics.iter().enumerate() {
+        for s in m.steps.iter() {
+            writeln!(
+                file,
+                "{},{},{},{},{},{}",
+                idx,
+                m.name,
+                s.turn,
+                s.position_error,
+                s.heading_error,
+                s.nees.map(|e| e.to_string()).unwrap_or_default()
+            )?;
+        }
+    }
+    file.flush()
+}
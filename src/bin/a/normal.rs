@@ -29,6 +29,12 @@ impl Normal {
     pub fn pdf(&self, x: f64) -> f64 {
         normal_pdf(x, self.mu, self.std)
     }
+    pub fn log_pdf(&self, x: f64) -> f64 {
+        normal_log_pdf(x, self.mu, self.std)
+    }
+    pub fn std(&self) -> f64 {
+        self.std
+    }
 }
 
 fn box_muller(rng: &mut Pcg64Mcg, mu: f64, std: f64) -> (f64, f64) {
@@ -41,9 +47,94 @@ fn box_muller(rng: &mut Pcg64Mcg, mu: f64, std: f64) -> (f64, f64) {
     )
 }
 
+// 標準正規分布の分位点関数(Acklamの有理関数近似, 相対誤差1.15e-9程度)
+pub fn standard_normal_quantile(p: f64) -> f64 {
+    assert!(0.0 < p && p < 1.0);
+    const A: [f64; 6] = [
+        -3.969683028665376e+01,
+        2.209460984245205e+02,
+        -2.759285104469687e+02,
+        1.38357751867269e+02,
+        -3.066479806614716e+01,
+        2.506628277459239e+00,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e+01,
+        1.615858368580409e+02,
+        -1.556989798598866e+02,
+        6.680131188771972e+01,
+        -1.328068155288572e+01,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-03,
+        -3.223964580411365e-01,
+        -2.400758277161838e+00,
+        -2.549732539343734e+00,
+        4.374664141464968e+00,
+        2.938163982698783e+00,
+    ];
+    const D: [f64; 4] = [
+        7.784695709041462e-03,
+        3.224671290700398e-01,
+        2.445134137142996e+00,
+        3.754408661907416e+00,
+    ];
+    let p_low = 0.02425;
+    if p < p_low {
+        let q = (-2.0 * p.ln()).sqrt();
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    } else if p <= 1.0 - p_low {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    } else {
+        -standard_normal_quantile(1.0 - p)
+    }
+}
+
+// 自由度kのカイ二乗分布の分位点関数(Wilson-Hilfertyの近似)
+pub fn chi_squared_quantile(p: f64, k: usize) -> f64 {
+    let k = k as f64;
+    let a = 2.0 / (9.0 * k);
+    k * (1.0 - a + standard_normal_quantile(p) * a.sqrt())
+        .max(0.0)
+        .powf(3.0)
+}
+
 fn normal_pdf(x: f64, mu: f64, std: f64) -> f64 {
     let v = (x - mu) / std;
     // 正確には以下だが、尤度計算において定数は不要
     // (-0.5 * v * v).exp() / ((2.0 * PI).sqrt() * std)
     (-0.5 * v * v).exp() / std
-}
\ No newline at end of file
+}
+
+// normal_pdfの対数(同じく定数は省く)
+fn normal_log_pdf(x: f64, mu: f64, std: f64) -> f64 {
+    let v = (x - mu) / std;
+    -0.5 * v * v - std.ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_normal_quantile_matches_table() {
+        // (p, Φ^-1(p))
+        let table = [
+            (0.5, 0.0),
+            (0.8413447460685429, 1.0),
+            (0.975, 1.9599639845400536),
+            (0.99, 2.3263478740408408),
+            (0.01, -2.3263478740408408),
+            (1e-4, -3.71901648545568),
+            (1.0 - 1e-6, 4.753424308817089),
+        ];
+        for &(p, z) in table.iter() {
+            let q = standard_normal_quantile(p);
+            assert!((q - z).abs() < 1e-8, "p = {}: {} != {}", p, q, z);
+        }
+    }
+}
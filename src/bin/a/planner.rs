@@ -0,0 +1,277 @@
+use crate::agent::Pose;
+use crate::common::Coord;
+use crate::control::ControlPolicy;
+use crate::estimator::MotionNoisePdf;
+use crate::motion::state_transition;
+use rand::prelude::*;
+use rand_pcg::Pcg64Mcg;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+// 通るとコストが増える長方形の水たまり
+#[derive(Debug, Clone, Copy)]
+pub struct Puddle {
+    pub lower_left: Coord,
+    pub upper_right: Coord,
+    pub depth: f64,
+}
+
+impl Puddle {
+    fn contains(&self, p: &Coord) -> bool {
+        self.lower_left.x <= p.x
+            && p.x <= self.upper_right.x
+            && self.lower_left.y <= p.y
+            && p.y <= self.upper_right.y
+    }
+}
+
+// 中心が入れば到達とみなす円形のゴール
+#[derive(Debug, Clone, Copy)]
+pub struct Goal {
+    pub coord: Coord,
+    pub radius: f64,
+}
+
+impl Goal {
+    pub fn contains(&self, p: &Coord) -> bool {
+        (p.x - self.coord.x).powi(2) + (p.y - self.coord.y).powi(2) <= self.radius.powi(2)
+    }
+}
+
+// 世界(原点を中心とするwidth x height)をx, y, 向きで区切った格子
+#[derive(Debug, Clone, Copy)]
+pub struct Grid {
+    pub origin: Coord, // 左下の隅
+    pub xy_bin: f64,
+    pub theta_bin: f64, // 2πを割り切るように丸めた幅
+    pub nx: usize,
+    pub ny: usize,
+    pub nt: usize,
+}
+
+impl Grid {
+    pub fn new(width: f64, height: f64, xy_bin: f64, theta_bin: f64) -> Self {
+        let nt = ((2.0 * PI / theta_bin).round() as usize).max(1);
+        Self {
+            origin: Coord {
+                x: -width / 2.0,
+                y: -height / 2.0,
+            },
+            xy_bin,
+            theta_bin: 2.0 * PI / nt as f64,
+            nx: ((width / xy_bin).ceil() as usize).max(1),
+            ny: ((height / xy_bin).ceil() as usize).max(1),
+            nt,
+        }
+    }
+    pub fn state_num(&self) -> usize {
+        self.nx * self.ny * self.nt
+    }
+    // 姿勢を含む格子の番号(世界の外なら端の格子)
+    pub fn index(&self, pose: &Pose) -> usize {
+        let ix = self.clamp((pose.coord.x - self.origin.x) / self.xy_bin, self.nx);
+        let iy = self.clamp((pose.coord.y - self.origin.y) / self.xy_bin, self.ny);
+        self.flat(ix, iy, self.theta_index(pose.theta))
+    }
+    pub fn flat(&self, ix: usize, iy: usize, it: usize) -> usize {
+        (it * self.ny + iy) * self.nx + ix
+    }
+    // x, y方向の格子の中心
+    pub fn center(&self, ix: usize, iy: usize) -> Coord {
+        Coord {
+            x: self.origin.x + (ix as f64 + 0.5) * self.xy_bin,
+            y: self.origin.y + (iy as f64 + 0.5) * self.xy_bin,
+        }
+    }
+    fn theta_index(&self, theta: f64) -> usize {
+        (theta.rem_euclid(2.0 * PI) / self.theta_bin) as usize % self.nt
+    }
+    fn clamp(&self, i: f64, n: usize) -> usize {
+        (i.floor().max(0.0) as usize).min(n - 1)
+    }
+}
+
+// 格子上の価値反復の設定
+// 行動は前進(nu, 0), 左旋回(0, omega), 右旋回(0, -omega)の3つで,
+// 1ステップのコストはtime_interval * (1 + puddle_coef * 移動先の水たまりの深さ)
+#[derive(Debug, Clone)]
+pub struct Planner {
+    pub grid: Grid,
+    pub goal: Goal,
+    pub puddles: Vec<Puddle>,
+    pub time_interval: f64,
+    pub nu: f64,
+    pub omega: f64,
+    pub puddle_coef: f64,
+    pub samples: usize,    // 状態遷移確率を求めるために1つの格子から動かす姿勢の数
+    pub max_sweeps: usize, // 価値の変化がtoleranceを下回らなくてもここで打ち切る
+    pub tolerance: f64,
+}
+
+// 価値反復で求めた状態価値と方策
+#[derive(Debug)]
+pub struct Plan {
+    pub planner: Planner,
+    pub actions: Vec<(f64, f64)>,
+    pub value: Vec<f64>, // ゴールまでの期待コストの符号を反転したもの(ゴール内は0)
+    pub policy: Vec<usize>, // 各状態で選ぶ行動(actionsの添字)
+    pub sweeps: usize,
+    pub converged: bool,
+}
+
+// 格子の左下の隅からの, 移動先の格子のずれ(x, y)と向きの格子の番号
+type Outcome = ((i64, i64, usize), f64);
+
+impl Planner {
+    // 状態遷移確率を推定器の動作の雑音から標本化して価値反復を解く
+    pub fn solve(self, noise: &mut MotionNoisePdf, rng: &mut Pcg64Mcg) -> Plan {
+        let grid = self.grid;
+        let actions = vec![
+            (self.nu.abs(), 0.0),
+            (0.0, self.omega.abs()),
+            (0.0, -self.omega.abs()),
+        ];
+        // 状態遷移は位置によらないので, 向きの格子と行動ごとに求める
+        let transitions: Vec<Vec<Vec<Outcome>>> = (0..grid.nt)
+            .map(|it| {
+                actions
+                    .iter()
+                    .map(|&(nu, omega)| self.sample_transition(noise, rng, it, nu, omega))
+                    .collect()
+            })
+            .collect();
+
+        let mut cost = vec![0.0; grid.nx * grid.ny];
+        let mut terminal = vec![false; grid.nx * grid.ny];
+        for iy in 0..grid.ny {
+            for ix in 0..grid.nx {
+                let center = grid.center(ix, iy);
+                let depth: f64 = self
+                    .puddles
+                    .iter()
+                    .filter(|p| p.contains(&center))
+                    .map(|p| p.depth)
+                    .sum();
+                cost[iy * grid.nx + ix] = self.time_interval * (1.0 + self.puddle_coef * depth);
+                terminal[iy * grid.nx + ix] = self.goal.contains(&center);
+            }
+        }
+
+        let mut value = vec![0.0; grid.state_num()];
+        let mut policy = vec![0; grid.state_num()];
+        let mut sweeps = 0;
+        let mut converged = false;
+        while sweeps < self.max_sweeps && !converged {
+            sweeps += 1;
+            let mut delta: f64 = 0.0;
+            for (it, actions) in transitions.iter().enumerate() {
+                for iy in 0..grid.ny {
+                    for ix in 0..grid.nx {
+                        if terminal[iy * grid.nx + ix] {
+                            continue;
+                        }
+                        let s = grid.flat(ix, iy, it);
+                        let mut best = (f64::NEG_INFINITY, policy[s]);
+                        for (a, outcomes) in actions.iter().enumerate() {
+                            // 同じ格子に留まる確率は価値の式を解いて取り除き, 収束を速める
+                            let mut stay = 0.0;
+                            let mut q = 0.0;
+                            for &((dx, dy, jt), p) in outcomes.iter() {
+                                let jx = (ix as i64 + dx).clamp(0, grid.nx as i64 - 1) as usize;
+                                let jy = (iy as i64 + dy).clamp(0, grid.ny as i64 - 1) as usize;
+                                let j = jy * grid.nx + jx;
+                                let next = grid.flat(jx, jy, jt);
+                                if next == s {
+                                    stay += p;
+                                    q -= p * cost[j];
+                                } else {
+                                    q += p * (value[next] - cost[j]);
+                                }
+                            }
+                            if stay < 1.0 {
+                                q /= 1.0 - stay;
+                            } else {
+                                q = f64::NEG_INFINITY;
+                            }
+                            if q > best.0 {
+                                best = (q, a);
+                            }
+                        }
+                        delta = delta.max((best.0 - value[s]).abs());
+                        value[s] = best.0;
+                        policy[s] = best.1;
+                    }
+                }
+            }
+            converged = delta < self.tolerance;
+        }
+        Plan {
+            planner: self,
+            actions,
+            value,
+            policy,
+            sweeps,
+            converged,
+        }
+    }
+    // 向きの格子itの中の一様な姿勢から1ステップ動かしたときの, 移動先の格子の分布
+    fn sample_transition(
+        &self,
+        noise: &mut MotionNoisePdf,
+        rng: &mut Pcg64Mcg,
+        it: usize,
+        nu: f64,
+        omega: f64,
+    ) -> Vec<Outcome> {
+        let grid = &self.grid;
+        let mut counts: HashMap<(i64, i64, usize), usize> = HashMap::new();
+        for _ in 0..self.samples {
+            let pose = Pose {
+                coord: Coord {
+                    x: rng.gen::<f64>() * grid.xy_bin,
+                    y: rng.gen::<f64>() * grid.xy_bin,
+                },
+                theta: (it as f64 + rng.gen::<f64>()) * grid.theta_bin,
+            };
+            let (nu, omega) = noise.noised_control(rng, nu, omega, self.time_interval);
+            let next = state_transition(self.time_interval, pose, nu, omega);
+            let key = (
+                (next.coord.x / grid.xy_bin).floor() as i64,
+                (next.coord.y / grid.xy_bin).floor() as i64,
+                grid.theta_index(next.theta),
+            );
+            *counts.entry(key).or_insert(0) += 1;
+        }
+        counts
+            .into_iter()
+            .map(|(key, n)| (key, n as f64 / self.samples as f64))
+            .collect()
+    }
+}
+
+impl Plan {
+    // x, y方向の格子ごとの, 向きについて最大の状態価値(ヒートマップ用)
+    pub fn max_value(&self, ix: usize, iy: usize) -> f64 {
+        let grid = &self.planner.grid;
+        (0..grid.nt)
+            .map(|it| self.value[grid.flat(ix, iy, it)])
+            .fold(f64::NEG_INFINITY, f64::max)
+    }
+}
+
+// 推定姿勢の格子で価値反復の方策が選ぶ行動をとり, ゴールに入ったら止まる
+#[derive(Debug, Clone)]
+pub struct PlanPolicy {
+    pub plan: Rc<Plan>,
+}
+
+impl ControlPolicy for PlanPolicy {
+    fn control(&mut self, _turn: usize, estimate: &Pose) -> (f64, f64) {
+        if self.plan.planner.goal.contains(&estimate.coord) {
+            return (0.0, 0.0);
+        }
+        let s = self.plan.planner.grid.index(estimate);
+        self.plan.actions[self.plan.policy[s]]
+    }
+}
@@ -0,0 +1,160 @@
+use rand::Rng;
+use rand_pcg::Pcg64Mcg;
+use std::f64::consts::PI;
+
+use crate::agent::Pose;
+use crate::camera::Observation;
+use crate::common::{convert_radian_in_range, Coord};
+use crate::estimator::Particle;
+use crate::normal::Normal;
+
+// 推定が破綻した(誘拐された)ときの復帰方法
+// thresholdはパーティクルの平均尤度の閾値で, 下回るとリセットする
+#[derive(Debug, Clone, Copy)]
+pub enum ResetStrategy {
+    // 全パーティクルを環境中に一様にばらまく
+    Random {
+        threshold: f64,
+    },
+    // 最も近いランドマークの観測と矛盾しない姿勢に撒き直す
+    Sensor {
+        threshold: f64,
+    },
+    // 各パーティクルに雑音を加えて分布を広げ, 観測で重みを付け直す
+    Expansion {
+        threshold: f64,
+        xy_std: f64,
+        theta_std: f64,
+    },
+    // 尤度の短期平均と長期平均の比に応じてランダムなパーティクルを混ぜる
+    Augmented {
+        alpha_slow: f64,
+        alpha_fast: f64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResetEvent {
+    Random,
+    Sensor,
+    Expansion,
+    Augmented { injected: usize },
+}
+
+impl std::fmt::Display for ResetEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResetEvent::Random => write!(f, "random reset")?,
+            ResetEvent::Sensor => write!(f, "sensor reset")?,
+            ResetEvent::Expansion => write!(f, "expansion reset")?,
+            ResetEvent::Augmented { injected } => {
+                write!(f, "augmented MCL ({} injected)", injected)?
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct Reset {
+    pub strategy: ResetStrategy,
+    pub width: f64,
+    pub height: f64,
+    pub w_slow: f64, // 尤度の長期平均(Augmented MCL)
+    pub w_fast: f64, // 尤度の短期平均(Augmented MCL)
+}
+
+impl Reset {
+    pub fn new(strategy: ResetStrategy, width: f64, height: f64) -> Self {
+        Self {
+            strategy,
+            width,
+            height,
+            w_slow: 0.0,
+            w_fast: 0.0,
+        }
+    }
+    pub fn threshold(&self) -> Option<f64> {
+        match self.strategy {
+            ResetStrategy::Random { threshold }
+            | ResetStrategy::Sensor { threshold }
+            | ResetStrategy::Expansion { threshold, .. } => Some(threshold),
+            ResetStrategy::Augmented { .. } => None,
+        }
+    }
+    pub fn random_pose(&self, rng: &mut Pcg64Mcg) -> Pose {
+        Pose {
+            coord: Coord {
+                x: rng.gen_range(-self.width / 2.0..=self.width / 2.0),
+                y: rng.gen_range(-self.height / 2.0..=self.height / 2.0),
+            },
+            theta: rng.gen_range(-PI..PI),
+        }
+    }
+    pub fn random_reset(&self, rng: &mut Pcg64Mcg, particles: &mut [Particle]) {
+        for particle in particles.iter_mut() {
+            particle.pose = self.random_pose(rng);
+            particle.log_weight = 0.0;
+        }
+    }
+    pub fn sensor_reset(
+        &self,
+        rng: &mut Pcg64Mcg,
+        particles: &mut [Particle],
+        obs: &Observation,
+        mark: &Coord,
+        distance_rate_std: f64,
+        direction_std: f64,
+    ) {
+        let mut distance_normal = Normal::new(obs.dist, obs.dist * distance_rate_std);
+        let mut direction_normal = Normal::new(obs.angle, direction_std);
+        for particle in particles.iter_mut() {
+            // ランドマークから見たロボットの方角と距離を選ぶ
+            let psi = rng.gen_range(-PI..PI);
+            let dist = distance_normal.sample(rng);
+            particle.pose.coord.x = mark.x + dist * psi.cos();
+            particle.pose.coord.y = mark.y + dist * psi.sin();
+            // ランドマークが観測した向きに見えるようにロボットの向きを決める
+            let angle = direction_normal.sample(rng);
+            particle.pose.theta = convert_radian_in_range(
+                (mark.y - particle.pose.coord.y).atan2(mark.x - particle.pose.coord.x) - angle,
+            );
+            particle.log_weight = 0.0;
+        }
+    }
+    pub fn expansion_reset(&self, rng: &mut Pcg64Mcg, particles: &mut [Particle]) {
+        if let ResetStrategy::Expansion {
+            xy_std, theta_std, ..
+        } = self.strategy
+        {
+            let mut xy_normal = Normal::new(0.0, xy_std);
+            let mut theta_normal = Normal::new(0.0, theta_std);
+            for particle in particles.iter_mut() {
+                particle.pose.coord.x += xy_normal.sample(rng);
+                particle.pose.coord.y += xy_normal.sample(rng);
+                particle.pose.theta += theta_normal.sample(rng);
+                particle.log_weight = 0.0;
+            }
+        }
+    }
+    // 平均尤度で短期・長期平均を更新し, ランダムなパーティクルを混ぜる確率を返す
+    pub fn update_average_likelihood(&mut self, likelihood: f64) -> f64 {
+        if let ResetStrategy::Augmented {
+            alpha_slow,
+            alpha_fast,
+        } = self.strategy
+        {
+            if self.w_slow == 0.0 && self.w_fast == 0.0 {
+                self.w_slow = likelihood;
+                self.w_fast = likelihood;
+            } else {
+                self.w_slow += alpha_slow * (likelihood - self.w_slow);
+                self.w_fast += alpha_fast * (likelihood - self.w_fast);
+            }
+            if self.w_slow > 0.0 {
+                return (1.0 - self.w_fast / self.w_slow).max(0.0);
+            }
+        }
+        0.0
+    }
+}
@@ -0,0 +1,1311 @@
+use crate::agent::{Agent, Pose};
+use crate::association::Association;
+use crate::bias_ekf::BiasEkf;
+use crate::camera::IdMode;
+use crate::common::Coord;
+use crate::control::{ConstantControl, ControlPolicy, GoToPose, PurePursuit, Schedule, Segment};
+use crate::ekf::EkfEstimator;
+use crate::ekf_slam::EkfSlam;
+use crate::estimator::{Estimator, Filter, KldConfig, MotionNoisePdf, Resampling};
+use crate::fast_slam::{FastSlam, FastSlamVersion};
+use crate::graph_slam::{GraphSlam, GraphSlamMethod};
+use crate::likelihood::ObservationModel;
+use crate::planner::{Goal, Grid, Plan, PlanPolicy, Planner, Puddle};
+use crate::reset::ResetStrategy;
+use crate::ukf::UkfEstimator;
+use crate::Input;
+use rand::SeedableRng;
+use rand_pcg::Pcg64Mcg;
+use serde::Deserialize;
+use std::f64::consts::PI;
+use std::path::Path;
+use std::rc::Rc;
+
+// シナリオファイル(TOML)の内容
+// 省略したキーは従来main.rsに直書きしていた値になる
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+    pub world: WorldConfig,
+    pub robot: RobotConfig,
+    pub agent: AgentConfig,
+    pub estimator: EstimatorConfig,
+    pub graph_slam: GraphSlamConfig,
+    pub control: ControlConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorldConfig {
+    pub time_span: f64,     // sec
+    pub time_interval: f64, // sec
+    pub width: usize,
+    pub height: usize,
+    pub landmarks: Vec<[f64; 2]>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RobotConfig {
+    pub init_pose: [f64; 3], // x, y, theta
+    pub radius: f64,         // ロボット半径, m
+    pub nu: f64,             // ロボットの前方方向の速度, m/s
+    pub omega: f64,          // ロボットの中心の角速度, rad/s
+}
+
+// エージェントと推定器に与える制御指令
+// constantはrobot.nu, robot.omegaのまま円弧を描く
+// scheduleは区間ごとの指令を[[segments]]に並べたTOMLファイル(シナリオファイルからの相対パス)に従う
+// waypointsはrobot.nu, robot.omegaの大きさでその場旋回と直進を繰り返して順に向かう(推定姿勢は見ない)
+// pure_pursuitとgo_to_poseはestimator.kindsの最初の推定器の推定姿勢から指令を決める閉ループ制御で,
+// 速さはrobot.nu, 角速度の大きさはrobot.omegaを上限にする
+// value_iterationは世界を格子に区切って価値反復で求めた方策に, 同じく推定姿勢から従う
+#[derive(Debug, Default, Deserialize)]
+#[serde(tag = "policy", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ControlConfig {
+    #[default]
+    Constant,
+    Schedule {
+        file: String,
+        #[serde(skip)]
+        segments: Vec<SegmentConfig>, // fileから読み込んだ区間
+    },
+    Waypoints {
+        waypoints: Vec<[f64; 2]>,
+    },
+    PurePursuit {
+        waypoints: Vec<[f64; 2]>,
+        lookahead: f64,      // m
+        goal_tolerance: f64, // m
+    },
+    GoToPose {
+        poses: Vec<[f64; 3]>, // x, y, theta
+        k_rho: f64,
+        k_alpha: f64,
+        k_beta: f64,
+        goal_tolerance: f64, // m
+    },
+    ValueIteration {
+        goal: [f64; 2],
+        goal_radius: f64, // m
+        #[serde(default)]
+        puddles: Vec<PuddleConfig>,
+        puddle_coef: f64,
+        xy_bin: f64,    // m
+        theta_bin: f64, // rad
+        samples: usize,
+        max_sweeps: usize,
+        tolerance: f64,
+        #[serde(skip)]
+        plan: Option<Rc<Plan>>, // 読み込み時に解いた方策(試行ごとに解き直さない)
+    },
+}
+
+// 価値反復で通るとコストが増える長方形の領域
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PuddleConfig {
+    pub lower_left: [f64; 2],
+    pub upper_right: [f64; 2],
+    pub depth: f64,
+}
+
+// 制御指令の予定表ファイル
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleConfig {
+    pub segments: Vec<SegmentConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SegmentConfig {
+    pub duration: f64, // sec
+    pub nu: f64,       // m/s
+    pub omega: f64,    // rad/s
+}
+
+// エージェントに与える雑音・故障の設定(実際は未知のパラメータ)
+// Noneの項目は理想的な動作・観測になる
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentConfig {
+    pub seed: u64,
+    pub motion_noise: Option<MotionNoiseConfig>,
+    pub motion_bias: Option<MotionBiasConfig>,
+    pub stuck: Option<StuckConfig>,
+    pub kidnap: Option<KidnapConfig>,
+    pub camera_noise: Option<CameraNoiseConfig>,
+    pub camera_bias: Option<CameraBiasConfig>,
+    pub phantom: Option<ProbConfig>,
+    pub oversight: Option<ProbConfig>,
+    pub occlusion: Option<ProbConfig>,
+    pub camera_ids: CameraIdsConfig,
+}
+
+// 観測に付けるランドマークのid
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CameraIdsConfig {
+    #[default]
+    Known,
+    Stripped,
+    Scrambled,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MotionNoiseConfig {
+    pub noise_per_meter: f64,
+    pub noise_std: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MotionBiasConfig {
+    pub nu_bias_rate_std: f64,
+    pub omega_bias_rate_std: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StuckConfig {
+    pub expected_stuck_time: f64,
+    pub expected_escape_time: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KidnapConfig {
+    pub expected_kidnap_time: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraNoiseConfig {
+    pub distance_noise_rate: f64,
+    pub direction_noise: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraBiasConfig {
+    pub distance_bias_rate_std: f64,
+    pub direction_bias_std: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProbConfig {
+    pub prob: f64,
+}
+
+// 推定器の設定
+// 各ノイズの標準偏差は実験により得た値(--calibrateでエージェントの設定から推定できる)
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EstimatorConfig {
+    pub seed: u64,
+    pub kinds: Vec<String>,
+    pub particle_num: usize,
+    pub nn_std: f64,
+    pub no_std: f64,
+    pub on_std: f64,
+    pub oo_std: f64,
+    pub distance_rate_std: f64,
+    pub direction_std: f64,
+    pub kld: Option<KldSamplingConfig>,
+    pub reset: Option<ResetConfig>,
+    pub resampling: ResamplingConfig,
+    pub association: AssociationConfig,
+    pub likelihood: LikelihoodConfig,
+    pub bias: BiasPriorConfig,
+}
+
+// MCLとEKFでの観測とランドマークの対応付け
+// confidenceはマハラノビス距離のゲートに使うカイ二乗分布の信頼度
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(tag = "strategy", rename_all = "snake_case", deny_unknown_fields)]
+pub enum AssociationConfig {
+    #[default]
+    Known,
+    MaximumLikelihood,
+    Gated {
+        confidence: f64,
+        reject_outliers: bool,
+    },
+    Jcbb {
+        confidence: f64,
+    },
+}
+
+// MCLでパーティクルの重み付けに使う観測の尤度のモデル
+// mixtureの各重みは合計が1になるよう正規化する
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(tag = "model", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LikelihoodConfig {
+    #[default]
+    Gaussian,
+    StudentT {
+        dof: f64,
+    },
+    Mixture {
+        z_hit: f64,
+        z_short: f64,
+        z_rand: f64,
+        lambda_short: f64,
+    },
+}
+
+// ekf_biasで推定するバイアスの事前分布の標準偏差(MotionBiasConfig, CameraBiasConfigと同じ意味)
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BiasPriorConfig {
+    pub nu_bias_rate_std: f64,
+    pub omega_bias_rate_std: f64,
+    pub distance_bias_rate_std: f64,
+    pub direction_bias_std: f64,
+}
+
+// MCLのリサンプリング方法
+// ess_thresholdを指定すると有効サンプルサイズがパーティクル数のその割合を下回ったときだけリサンプリングする
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResamplingConfig {
+    pub method: ResamplingMethod,
+    pub ess_threshold: Option<f64>,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResamplingMethod {
+    #[default]
+    Systematic,
+    Multinomial,
+    Stratified,
+    Residual,
+}
+
+// 指定するとMCLが推定の破綻から復帰できるようにする
+// thresholdはパーティクルの平均尤度に対する閾値
+#[derive(Debug, Deserialize)]
+#[serde(tag = "strategy", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ResetConfig {
+    Random {
+        threshold: f64,
+    },
+    Sensor {
+        threshold: f64,
+    },
+    Expansion {
+        threshold: f64,
+        xy_std: f64,
+        theta_std: f64,
+    },
+    Augmented {
+        alpha_slow: f64,
+        alpha_fast: f64,
+    },
+}
+
+// 走行後に記録全体を最適化するグラフベースSLAMの設定(ノイズの標準偏差はestimatorと共通)
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GraphSlamConfig {
+    pub method: GraphSlamMethodConfig,
+    pub max_iterations: usize,
+    pub tolerance: f64,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GraphSlamMethodConfig {
+    GaussNewton,
+    LevenbergMarquardt,
+}
+
+// 指定するとMCLのリサンプリングをKLDサンプリングにする
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KldSamplingConfig {
+    pub xy_bin: f64,
+    pub theta_bin: f64,
+    pub epsilon: f64,
+    pub delta: f64,
+    pub min_particle_num: usize,
+    pub max_particle_num: usize,
+}
+
+impl Default for GraphSlamConfig {
+    fn default() -> Self {
+        Self {
+            method: GraphSlamMethodConfig::LevenbergMarquardt,
+            max_iterations: 100,
+            tolerance: 1e-6,
+        }
+    }
+}
+
+impl Default for BiasPriorConfig {
+    fn default() -> Self {
+        Self {
+            nu_bias_rate_std: 0.1,
+            omega_bias_rate_std: 0.1,
+            distance_bias_rate_std: 0.1,
+            direction_bias_std: PI / 90.0,
+        }
+    }
+}
+
+impl Default for WorldConfig {
+    fn default() -> Self {
+        Self {
+            time_span: 30.0,
+            time_interval: 0.1,
+            width: 10,
+            height: 10,
+            landmarks: vec![[-4.0, 2.0], [2.0, -3.0], [3.0, 3.0]],
+        }
+    }
+}
+
+impl Default for RobotConfig {
+    fn default() -> Self {
+        Self {
+            init_pose: [0.0, 0.0, 0.0],
+            radius: 0.2,
+            nu: 0.2,
+            omega: 10.0_f64.to_radians(),
+        }
+    }
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            motion_noise: Some(MotionNoiseConfig {
+                noise_per_meter: 5.0,
+                noise_std: PI / 60.0,
+            }),
+            motion_bias: None,
+            stuck: None,
+            kidnap: None,
+            camera_noise: Some(CameraNoiseConfig {
+                distance_noise_rate: 0.1,
+                direction_noise: PI / 90.0,
+            }),
+            camera_bias: None,
+            phantom: None,
+            oversight: None,
+            occlusion: None,
+            camera_ids: CameraIdsConfig::Known,
+        }
+    }
+}
+
+impl Default for EstimatorConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            kinds: vec!["mcl".to_string(), "ekf".to_string()],
+            particle_num: 100,
+            nn_std: 0.19,
+            no_std: 0.001,
+            on_std: 0.13,
+            oo_std: 0.2,
+            distance_rate_std: 0.14,
+            direction_std: 0.05,
+            kld: None,
+            reset: None,
+            resampling: ResamplingConfig::default(),
+            association: AssociationConfig::default(),
+            likelihood: LikelihoodConfig::default(),
+            bias: BiasPriorConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(String, std::io::Error),
+    Parse(String, toml::de::Error),
+    Invalid { key: String, message: String },
+}
+
+impl std::fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScenarioError::Io(path, e) => write!(f, "{}: {}", path, e)?,
+            ScenarioError::Parse(path, e) => write!(f, "{}: {}", path, e)?,
+            ScenarioError::Invalid { key, message } => write!(f, "`{}`: {}", key, message)?,
+        }
+        Ok(())
+    }
+}
+
+impl ControlConfig {
+    // 推定姿勢から制御指令を決める方策か
+    pub fn is_closed_loop(&self) -> bool {
+        matches!(
+            self,
+            Self::PurePursuit { .. } | Self::GoToPose { .. } | Self::ValueIteration { .. }
+        )
+    }
+}
+
+fn coords(points: &[[f64; 2]]) -> Vec<Coord> {
+    points.iter().map(|&[x, y]| Coord { x, y }).collect()
+}
+
+fn invalid(key: &str, message: &str) -> ScenarioError {
+    ScenarioError::Invalid {
+        key: key.to_string(),
+        message: message.to_string(),
+    }
+}
+
+fn check_positive(key: &str, value: f64) -> Result<(), ScenarioError> {
+    if value.is_finite() && value > 0.0 {
+        Ok(())
+    } else {
+        Err(invalid(key, &format!("must be positive, got {}", value)))
+    }
+}
+
+fn check_non_negative(key: &str, value: f64) -> Result<(), ScenarioError> {
+    if value.is_finite() && value >= 0.0 {
+        Ok(())
+    } else {
+        Err(invalid(
+            key,
+            &format!("must be non-negative, got {}", value),
+        ))
+    }
+}
+
+fn check_prob(key: &str, value: f64) -> Result<(), ScenarioError> {
+    if (0.0..=1.0).contains(&value) {
+        Ok(())
+    } else {
+        Err(invalid(key, &format!("must be in [0, 1], got {}", value)))
+    }
+}
+
+impl Scenario {
+    pub const ESTIMATOR_KINDS: [&'static str; 7] = [
+        "mcl",
+        "ekf",
+        "ukf",
+        "ekf_bias",
+        "ekf_slam",
+        "fast_slam1",
+        "fast_slam2",
+    ];
+
+    pub fn load(path: &str) -> Result<Self, ScenarioError> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| ScenarioError::Io(path.to_string(), e))?;
+        let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        Self::parse_in(&text, dir).map_err(|e| match e {
+            ScenarioError::Parse(p, e) if p.is_empty() => ScenarioError::Parse(path.to_string(), e),
+            e => e,
+        })
+    }
+    // 制御指令の予定表ファイルはカレントディレクトリからの相対パスとして読む
+    pub fn parse(text: &str) -> Result<Self, ScenarioError> {
+        Self::parse_in(text, Path::new(""))
+    }
+    fn parse_in(text: &str, dir: &Path) -> Result<Self, ScenarioError> {
+        let mut scenario: Scenario =
+            toml::from_str(text).map_err(|e| ScenarioError::Parse(String::new(), e))?;
+        scenario.load_schedule(dir)?;
+        scenario.validate()?;
+        scenario.solve_plan();
+        Ok(scenario)
+    }
+    // 価値反復の状態遷移確率は推定器の動作の雑音(nn_std, no_std, on_std, oo_std)から標本化する
+    fn solve_plan(&mut self) {
+        let input = self.input();
+        let mut noise = self.motion_noise_pdf();
+        let mut rng = Pcg64Mcg::seed_from_u64(self.estimator.seed);
+        if let ControlConfig::ValueIteration {
+            goal,
+            goal_radius,
+            puddles,
+            puddle_coef,
+            xy_bin,
+            theta_bin,
+            samples,
+            max_sweeps,
+            tolerance,
+            plan,
+        } = &mut self.control
+        {
+            let planner = Planner {
+                grid: Grid::new(input.width as f64, input.height as f64, *xy_bin, *theta_bin),
+                goal: Goal {
+                    coord: Coord {
+                        x: goal[0],
+                        y: goal[1],
+                    },
+                    radius: *goal_radius,
+                },
+                puddles: puddles
+                    .iter()
+                    .map(|c| Puddle {
+                        lower_left: Coord {
+                            x: c.lower_left[0],
+                            y: c.lower_left[1],
+                        },
+                        upper_right: Coord {
+                            x: c.upper_right[0],
+                            y: c.upper_right[1],
+                        },
+                        depth: c.depth,
+                    })
+                    .collect(),
+                time_interval: input.time_interval,
+                nu: input.nu,
+                omega: input.omega,
+                puddle_coef: *puddle_coef,
+                samples: *samples,
+                max_sweeps: *max_sweeps,
+                tolerance: *tolerance,
+            };
+            *plan = Some(Rc::new(planner.solve(&mut noise, &mut rng)));
+        }
+    }
+    // 価値反復で解いた方策(ビジュアライザで価値関数を重ねて表示する)
+    pub fn plan(&self) -> Option<Rc<Plan>> {
+        match &self.control {
+            ControlConfig::ValueIteration { plan, .. } => plan.clone(),
+            _ => None,
+        }
+    }
+    fn load_schedule(&mut self, dir: &Path) -> Result<(), ScenarioError> {
+        if let ControlConfig::Schedule { file, segments } = &mut self.control {
+            let path = dir.join(file).to_string_lossy().to_string();
+            let text =
+                std::fs::read_to_string(&path).map_err(|e| ScenarioError::Io(path.clone(), e))?;
+            let schedule: ScheduleConfig =
+                toml::from_str(&text).map_err(|e| ScenarioError::Parse(path, e))?;
+            *segments = schedule.segments;
+        }
+        Ok(())
+    }
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        let world = &self.world;
+        check_positive("world.time_span", world.time_span)?;
+        check_positive("world.time_interval", world.time_interval)?;
+        check_positive("world.width", world.width as f64)?;
+        check_positive("world.height", world.height as f64)?;
+        for (i, mark) in world.landmarks.iter().enumerate() {
+            if !mark.iter().all(|v| v.is_finite()) {
+                return Err(invalid(
+                    &format!("world.landmarks[{}]", i),
+                    "must be finite",
+                ));
+            }
+        }
+
+        let robot = &self.robot;
+        if !robot.init_pose.iter().all(|v| v.is_finite()) {
+            return Err(invalid("robot.init_pose", "must be finite"));
+        }
+        check_positive("robot.radius", robot.radius)?;
+        if !robot.nu.is_finite() {
+            return Err(invalid("robot.nu", "must be finite"));
+        }
+        if !robot.omega.is_finite() {
+            return Err(invalid("robot.omega", "must be finite"));
+        }
+
+        match &self.control {
+            ControlConfig::Constant => {}
+            ControlConfig::ValueIteration {
+                goal,
+                goal_radius,
+                puddles,
+                puddle_coef,
+                xy_bin,
+                theta_bin,
+                samples,
+                max_sweeps,
+                tolerance,
+                ..
+            } => {
+                let (half_width, half_height) = (
+                    self.world.width as f64 / 2.0,
+                    self.world.height as f64 / 2.0,
+                );
+                if !(goal[0].abs() <= half_width && goal[1].abs() <= half_height) {
+                    return Err(invalid("control.goal", "must be inside the world"));
+                }
+                check_positive("control.xy_bin", *xy_bin)?;
+                check_positive("control.theta_bin", *theta_bin)?;
+                // 半径がこれ以上あればゴールに格子の中心が必ず入る
+                check_positive("control.goal_radius", *goal_radius)?;
+                if *goal_radius < xy_bin * std::f64::consts::FRAC_1_SQRT_2 {
+                    return Err(invalid(
+                        "control.goal_radius",
+                        "must be at least xy_bin / sqrt(2)",
+                    ));
+                }
+                for (i, c) in puddles.iter().enumerate() {
+                    let key = format!("control.puddles[{}]", i);
+                    if !c
+                        .lower_left
+                        .iter()
+                        .chain(&c.upper_right)
+                        .all(|v| v.is_finite())
+                        || c.lower_left[0] > c.upper_right[0]
+                        || c.lower_left[1] > c.upper_right[1]
+                    {
+                        return Err(invalid(&key, "lower_left must not exceed upper_right"));
+                    }
+                    check_non_negative(&format!("{}.depth", key), c.depth)?;
+                }
+                check_non_negative("control.puddle_coef", *puddle_coef)?;
+                if *samples == 0 {
+                    return Err(invalid("control.samples", "must be at least 1"));
+                }
+                if *max_sweeps == 0 {
+                    return Err(invalid("control.max_sweeps", "must be at least 1"));
+                }
+                check_positive("control.tolerance", *tolerance)?;
+            }
+            ControlConfig::Schedule { segments, .. } => {
+                if segments.is_empty() {
+                    return Err(invalid("control.file", "needs at least 1 segment"));
+                }
+                for (i, c) in segments.iter().enumerate() {
+                    let key = format!("control.file: segments[{}]", i);
+                    check_positive(&format!("{}.duration", key), c.duration)?;
+                    if !c.nu.is_finite() || !c.omega.is_finite() {
+                        return Err(invalid(&key, "nu and omega must be finite"));
+                    }
+                }
+            }
+            ControlConfig::Waypoints { waypoints } => {
+                if waypoints.is_empty() {
+                    return Err(invalid("control.waypoints", "needs at least 1 waypoint"));
+                }
+                if !waypoints.iter().flatten().all(|v| v.is_finite()) {
+                    return Err(invalid("control.waypoints", "must be finite"));
+                }
+                if robot.nu == 0.0 || robot.omega == 0.0 {
+                    return Err(invalid(
+                        "control.waypoints",
+                        "needs nonzero robot.nu and robot.omega",
+                    ));
+                }
+            }
+            ControlConfig::PurePursuit {
+                waypoints,
+                lookahead,
+                goal_tolerance,
+            } => {
+                if waypoints.is_empty() {
+                    return Err(invalid("control.waypoints", "needs at least 1 waypoint"));
+                }
+                if !waypoints.iter().flatten().all(|v| v.is_finite()) {
+                    return Err(invalid("control.waypoints", "must be finite"));
+                }
+                check_positive("control.lookahead", *lookahead)?;
+                check_positive("control.goal_tolerance", *goal_tolerance)?;
+            }
+            ControlConfig::GoToPose {
+                poses,
+                k_rho,
+                k_alpha,
+                k_beta,
+                goal_tolerance,
+            } => {
+                if poses.is_empty() {
+                    return Err(invalid("control.poses", "needs at least 1 pose"));
+                }
+                if !poses.iter().flatten().all(|v| v.is_finite()) {
+                    return Err(invalid("control.poses", "must be finite"));
+                }
+                check_positive("control.k_rho", *k_rho)?;
+                if !(k_alpha.is_finite() && k_alpha > k_rho) {
+                    return Err(invalid("control.k_alpha", "must be greater than k_rho"));
+                }
+                if !(k_beta.is_finite() && *k_beta < 0.0) {
+                    return Err(invalid("control.k_beta", "must be negative"));
+                }
+                check_positive("control.goal_tolerance", *goal_tolerance)?;
+            }
+        }
+        if self.control.is_closed_loop() {
+            if robot.nu == 0.0 || robot.omega == 0.0 {
+                return Err(invalid("control", "needs nonzero robot.nu and robot.omega"));
+            }
+            if self.estimator.kinds.is_empty() {
+                return Err(invalid(
+                    "control",
+                    "needs at least 1 estimator in estimator.kinds",
+                ));
+            }
+        }
+
+        let agent = &self.agent;
+        if let Some(c) = &agent.motion_noise {
+            check_non_negative("agent.motion_noise.noise_per_meter", c.noise_per_meter)?;
+            check_non_negative("agent.motion_noise.noise_std", c.noise_std)?;
+        }
+        if let Some(c) = &agent.motion_bias {
+            check_non_negative("agent.motion_bias.nu_bias_rate_std", c.nu_bias_rate_std)?;
+            check_non_negative(
+                "agent.motion_bias.omega_bias_rate_std",
+                c.omega_bias_rate_std,
+            )?;
+        }
+        if let Some(c) = &agent.stuck {
+            check_positive("agent.stuck.expected_stuck_time", c.expected_stuck_time)?;
+            check_positive("agent.stuck.expected_escape_time", c.expected_escape_time)?;
+        }
+        if let Some(c) = &agent.kidnap {
+            check_positive("agent.kidnap.expected_kidnap_time", c.expected_kidnap_time)?;
+        }
+        if let Some(c) = &agent.camera_noise {
+            check_non_negative(
+                "agent.camera_noise.distance_noise_rate",
+                c.distance_noise_rate,
+            )?;
+            check_non_negative("agent.camera_noise.direction_noise", c.direction_noise)?;
+        }
+        if let Some(c) = &agent.camera_bias {
+            check_non_negative(
+                "agent.camera_bias.distance_bias_rate_std",
+                c.distance_bias_rate_std,
+            )?;
+            check_non_negative("agent.camera_bias.direction_bias_std", c.direction_bias_std)?;
+        }
+        if let Some(c) = &agent.phantom {
+            check_prob("agent.phantom.prob", c.prob)?;
+        }
+        if let Some(c) = &agent.oversight {
+            check_prob("agent.oversight.prob", c.prob)?;
+        }
+        if let Some(c) = &agent.occlusion {
+            check_prob("agent.occlusion.prob", c.prob)?;
+        }
+
+        let est = &self.estimator;
+        for (i, kind) in est.kinds.iter().enumerate() {
+            if !Self::ESTIMATOR_KINDS.contains(&kind.as_str()) {
+                return Err(invalid(
+                    &format!("estimator.kinds[{}]", i),
+                    &format!(
+                        "unknown estimator `{}`, expected one of {:?}",
+                        kind,
+                        Self::ESTIMATOR_KINDS
+                    ),
+                ));
+            }
+        }
+        if est.particle_num == 0 {
+            return Err(invalid("estimator.particle_num", "must be at least 1"));
+        }
+        check_positive("estimator.nn_std", est.nn_std)?;
+        check_positive("estimator.no_std", est.no_std)?;
+        check_positive("estimator.on_std", est.on_std)?;
+        check_positive("estimator.oo_std", est.oo_std)?;
+        check_positive("estimator.distance_rate_std", est.distance_rate_std)?;
+        check_positive("estimator.direction_std", est.direction_std)?;
+        if let Some(c) = &est.kld {
+            check_positive("estimator.kld.xy_bin", c.xy_bin)?;
+            check_positive("estimator.kld.theta_bin", c.theta_bin)?;
+            check_positive("estimator.kld.epsilon", c.epsilon)?;
+            if !(0.0 < c.delta && c.delta < 1.0) {
+                return Err(invalid(
+                    "estimator.kld.delta",
+                    &format!("must be in (0, 1), got {}", c.delta),
+                ));
+            }
+            if c.min_particle_num == 0 {
+                return Err(invalid(
+                    "estimator.kld.min_particle_num",
+                    "must be at least 1",
+                ));
+            }
+            if c.max_particle_num < c.min_particle_num {
+                return Err(invalid(
+                    "estimator.kld.max_particle_num",
+                    "must not be less than min_particle_num",
+                ));
+            }
+        }
+        check_positive("estimator.bias.nu_bias_rate_std", est.bias.nu_bias_rate_std)?;
+        check_positive(
+            "estimator.bias.omega_bias_rate_std",
+            est.bias.omega_bias_rate_std,
+        )?;
+        check_positive(
+            "estimator.bias.distance_bias_rate_std",
+            est.bias.distance_bias_rate_std,
+        )?;
+        check_positive(
+            "estimator.bias.direction_bias_std",
+            est.bias.direction_bias_std,
+        )?;
+        if let Some(ess_threshold) = est.resampling.ess_threshold {
+            check_prob("estimator.resampling.ess_threshold", ess_threshold)?;
+        }
+        match est.association {
+            AssociationConfig::Gated { confidence, .. }
+            | AssociationConfig::Jcbb { confidence } => {
+                if !(0.0 < confidence && confidence < 1.0) {
+                    return Err(invalid(
+                        "estimator.association.confidence",
+                        &format!("must be in (0, 1), got {}", confidence),
+                    ));
+                }
+            }
+            AssociationConfig::Known | AssociationConfig::MaximumLikelihood => {}
+        }
+        match est.likelihood {
+            LikelihoodConfig::Gaussian => {}
+            LikelihoodConfig::StudentT { dof } => {
+                check_positive("estimator.likelihood.dof", dof)?;
+            }
+            LikelihoodConfig::Mixture {
+                z_hit,
+                z_short,
+                z_rand,
+                lambda_short,
+            } => {
+                check_positive("estimator.likelihood.z_hit", z_hit)?;
+                check_non_negative("estimator.likelihood.z_short", z_short)?;
+                check_non_negative("estimator.likelihood.z_rand", z_rand)?;
+                check_positive("estimator.likelihood.lambda_short", lambda_short)?;
+            }
+        }
+        if agent.camera_ids == CameraIdsConfig::Stripped {
+            if let AssociationConfig::Known = est.association {
+                return Err(invalid(
+                    "estimator.association",
+                    "observations without ids need a strategy other than `known`",
+                ));
+            }
+        }
+        // SLAMの推定器は観測のidで地図のランドマークを区別する
+        if agent.camera_ids != CameraIdsConfig::Known {
+            if let Some(kind) = est.kinds.iter().find(|k| k.contains("slam")) {
+                return Err(invalid(
+                    "agent.camera_ids",
+                    &format!("estimator `{}` needs the true landmark ids", kind),
+                ));
+            }
+        }
+        if self.graph_slam.max_iterations == 0 {
+            return Err(invalid("graph_slam.max_iterations", "must be at least 1"));
+        }
+        check_positive("graph_slam.tolerance", self.graph_slam.tolerance)?;
+        match &est.reset {
+            Some(ResetConfig::Random { threshold }) | Some(ResetConfig::Sensor { threshold }) => {
+                check_non_negative("estimator.reset.threshold", *threshold)?;
+            }
+            Some(ResetConfig::Expansion {
+                threshold,
+                xy_std,
+                theta_std,
+            }) => {
+                check_non_negative("estimator.reset.threshold", *threshold)?;
+                check_positive("estimator.reset.xy_std", *xy_std)?;
+                check_positive("estimator.reset.theta_std", *theta_std)?;
+            }
+            Some(ResetConfig::Augmented {
+                alpha_slow,
+                alpha_fast,
+            }) => {
+                check_prob("estimator.reset.alpha_slow", *alpha_slow)?;
+                check_prob("estimator.reset.alpha_fast", *alpha_fast)?;
+                if alpha_slow >= alpha_fast {
+                    return Err(invalid(
+                        "estimator.reset.alpha_slow",
+                        "must be less than alpha_fast",
+                    ));
+                }
+            }
+            None => {}
+        }
+        Ok(())
+    }
+    pub fn input(&self) -> Input {
+        Input {
+            time_span: self.world.time_span,
+            time_interval: self.world.time_interval,
+            height: self.world.height,
+            width: self.world.width,
+            landmarks: self
+                .world
+                .landmarks
+                .iter()
+                .map(|&[x, y]| Coord { x, y })
+                .collect(),
+            init_pose: Pose {
+                coord: Coord {
+                    x: self.robot.init_pose[0],
+                    y: self.robot.init_pose[1],
+                },
+                theta: self.robot.init_pose[2],
+            },
+            radius: self.robot.radius,
+            nu: self.robot.nu,
+            omega: self.robot.omega,
+        }
+    }
+    pub fn build_control(&self, input: &Input) -> Box<dyn ControlPolicy> {
+        match &self.control {
+            ControlConfig::Constant => Box::new(ConstantControl {
+                nu: input.nu,
+                omega: input.omega,
+            }),
+            ControlConfig::Schedule { segments, .. } => Box::new(Schedule::new(
+                input.time_interval,
+                segments
+                    .iter()
+                    .map(|c| Segment {
+                        duration: c.duration,
+                        nu: c.nu,
+                        omega: c.omega,
+                    })
+                    .collect(),
+            )),
+            ControlConfig::Waypoints { waypoints } => Box::new(Schedule::from_waypoints(
+                input.time_interval,
+                input.init_pose,
+                &coords(waypoints),
+                input.nu,
+                input.omega,
+            )),
+            ControlConfig::PurePursuit {
+                waypoints,
+                lookahead,
+                goal_tolerance,
+            } => Box::new(PurePursuit::new(
+                input.init_pose,
+                &coords(waypoints),
+                input.nu,
+                input.omega,
+                *lookahead,
+                *goal_tolerance,
+            )),
+            ControlConfig::GoToPose {
+                poses,
+                k_rho,
+                k_alpha,
+                k_beta,
+                goal_tolerance,
+            } => Box::new(GoToPose::new(
+                poses
+                    .iter()
+                    .map(|&[x, y, theta]| Pose {
+                        coord: Coord { x, y },
+                        theta,
+                    })
+                    .collect(),
+                input.nu,
+                input.omega,
+                *k_rho,
+                *k_alpha,
+                *k_beta,
+                *goal_tolerance,
+            )),
+            ControlConfig::ValueIteration { plan, .. } => Box::new(PlanPolicy {
+                plan: plan.clone().expect("value iteration is solved on load"),
+            }),
+        }
+    }
+    // 経路を追従する方策なら, 初期位置からwaypoints(目標姿勢の位置)を結ぶ折れ線
+    pub fn reference_path(&self, input: &Input) -> Option<Vec<Coord>> {
+        let waypoints = match &self.control {
+            ControlConfig::Waypoints { waypoints } => coords(waypoints),
+            ControlConfig::PurePursuit { waypoints, .. } => coords(waypoints),
+            ControlConfig::GoToPose { poses, .. } => {
+                poses.iter().map(|&[x, y, _]| Coord { x, y }).collect()
+            }
+            _ => return None,
+        };
+        let mut path = vec![input.init_pose.coord];
+        path.extend(waypoints);
+        Some(path)
+    }
+    // seed_offsetは乱数シードをずらしてモンテカルロ試行するためのもの
+    pub fn build_agent(&self, input: &Input, seed_offset: u64) -> Agent {
+        let mut agent = Agent::new(
+            self.agent.seed + seed_offset,
+            input.time_interval,
+            input.init_pose,
+            input.radius,
+        );
+        let (width, height) = (input.width as f64, input.height as f64);
+        let c = &self.agent;
+        if let Some(c) = &c.motion_noise {
+            agent.set_motion_noise(c.noise_per_meter, c.noise_std);
+        }
+        if let Some(c) = &c.motion_bias {
+            agent.set_motion_bias(c.nu_bias_rate_std, c.omega_bias_rate_std);
+        }
+        if let Some(c) = &c.stuck {
+            agent.set_stuck(c.expected_stuck_time, c.expected_escape_time);
+        }
+        if let Some(c) = &c.kidnap {
+            agent.set_kidnap(c.expected_kidnap_time, width, height);
+        }
+        agent.set_camera_id_mode(match c.camera_ids {
+            CameraIdsConfig::Known => IdMode::Known,
+            CameraIdsConfig::Stripped => IdMode::Stripped,
+            CameraIdsConfig::Scrambled => IdMode::Scrambled,
+        });
+        if let Some(c) = &c.camera_noise {
+            agent.set_camera_noise(c.distance_noise_rate, c.direction_noise);
+        }
+        if let Some(c) = &c.camera_bias {
+            agent.set_camera_bias(c.distance_bias_rate_std, c.direction_bias_std);
+        }
+        if let Some(c) = &c.phantom {
+            agent.set_camera_phantom(c.prob, width, height);
+        }
+        if let Some(c) = &c.oversight {
+            agent.set_camera_oversight(c.prob);
+        }
+        if let Some(c) = &c.occlusion {
+            agent.set_camera_occlusion(c.prob);
+        }
+        agent
+    }
+    // キャリブレーション用に, 雑音とバイアスだけをシナリオのエージェントと同じにしたロボットを作る
+    // 故障(スタック・誘拐・ファントムなど)は起こさない
+    pub fn build_calibration_agent(
+        &self,
+        input: &Input,
+        seed_offset: u64,
+        init_pose: Pose,
+    ) -> Agent {
+        let mut agent = Agent::new(
+            self.agent.seed + seed_offset,
+            input.time_interval,
+            init_pose,
+            input.radius,
+        );
+        let c = &self.agent;
+        if let Some(c) = &c.motion_noise {
+            agent.set_motion_noise(c.noise_per_meter, c.noise_std);
+        }
+        if let Some(c) = &c.motion_bias {
+            agent.set_motion_bias(c.nu_bias_rate_std, c.omega_bias_rate_std);
+        }
+        if let Some(c) = &c.camera_noise {
+            agent.set_camera_noise(c.distance_noise_rate, c.direction_noise);
+        }
+        if let Some(c) = &c.camera_bias {
+            agent.set_camera_bias(c.distance_bias_rate_std, c.direction_bias_std);
+        }
+        agent
+    }
+    fn motion_noise_pdf(&self) -> MotionNoisePdf {
+        let est = &self.estimator;
+        MotionNoisePdf::new(est.nn_std, est.no_std, est.on_std, est.oo_std)
+    }
+    pub fn build_mcl(&self, input: &Input, seed_offset: u64) -> Estimator {
+        let est = &self.estimator;
+        let mut estimator = Estimator::new(
+            est.seed + seed_offset,
+            input.time_interval,
+            input.init_pose,
+            input.radius,
+            est.particle_num,
+            self.motion_noise_pdf(),
+            est.distance_rate_std,
+            est.direction_std,
+        );
+        if let Some(c) = &est.kld {
+            estimator.set_kld(KldConfig {
+                xy_bin: c.xy_bin,
+                theta_bin: c.theta_bin,
+                epsilon: c.epsilon,
+                delta: c.delta,
+                min_particle_num: c.min_particle_num,
+                max_particle_num: c.max_particle_num,
+            });
+        }
+        let resampling = match est.resampling.method {
+            ResamplingMethod::Systematic => Resampling::Systematic,
+            ResamplingMethod::Multinomial => Resampling::Multinomial,
+            ResamplingMethod::Stratified => Resampling::Stratified,
+            ResamplingMethod::Residual => Resampling::Residual,
+        };
+        estimator.set_resampling(resampling, est.resampling.ess_threshold);
+        estimator.set_association(self.association());
+        estimator.set_observation_model(match est.likelihood {
+            LikelihoodConfig::Gaussian => ObservationModel::Gaussian,
+            LikelihoodConfig::StudentT { dof } => ObservationModel::StudentT { dof },
+            LikelihoodConfig::Mixture {
+                z_hit,
+                z_short,
+                z_rand,
+                lambda_short,
+            } => ObservationModel::Mixture {
+                z_hit,
+                z_short,
+                z_rand,
+                lambda_short,
+            },
+        });
+        if let Some(c) = &est.reset {
+            let strategy = match *c {
+                ResetConfig::Random { threshold } => ResetStrategy::Random { threshold },
+                ResetConfig::Sensor { threshold } => ResetStrategy::Sensor { threshold },
+                ResetConfig::Expansion {
+                    threshold,
+                    xy_std,
+                    theta_std,
+                } => ResetStrategy::Expansion {
+                    threshold,
+                    xy_std,
+                    theta_std,
+                },
+                ResetConfig::Augmented {
+                    alpha_slow,
+                    alpha_fast,
+                } => ResetStrategy::Augmented {
+                    alpha_slow,
+                    alpha_fast,
+                },
+            };
+            estimator.set_reset(strategy, input.width as f64, input.height as f64);
+        }
+        estimator
+    }
+    fn association(&self) -> Association {
+        match self.estimator.association {
+            AssociationConfig::Known => Association::Known,
+            AssociationConfig::MaximumLikelihood => Association::MaximumLikelihood,
+            AssociationConfig::Gated {
+                confidence,
+                reject_outliers,
+            } => Association::Gated {
+                confidence,
+                reject_outliers,
+            },
+            AssociationConfig::Jcbb { confidence } => Association::Jcbb { confidence },
+        }
+    }
+    pub fn build_ekf(&self, input: &Input) -> EkfEstimator {
+        let est = &self.estimator;
+        let mut estimator = EkfEstimator::new(
+            input.time_interval,
+            input.init_pose,
+            input.radius,
+            self.motion_noise_pdf(),
+            est.distance_rate_std,
+            est.direction_std,
+        );
+        estimator.set_association(self.association());
+        estimator
+    }
+    pub fn build_ukf(&self, input: &Input) -> UkfEstimator {
+        let est = &self.estimator;
+        let mut estimator = UkfEstimator::new(
+            input.time_interval,
+            input.init_pose,
+            input.radius,
+            self.motion_noise_pdf(),
+            est.distance_rate_std,
+            est.direction_std,
+        );
+        estimator.set_association(self.association());
+        estimator
+    }
+    pub fn build_bias_ekf(&self, input: &Input) -> BiasEkf {
+        let est = &self.estimator;
+        let mut estimator = BiasEkf::new(
+            input.time_interval,
+            input.init_pose,
+            input.radius,
+            self.motion_noise_pdf(),
+            est.distance_rate_std,
+            est.direction_std,
+            [
+                est.bias.nu_bias_rate_std,
+                est.bias.omega_bias_rate_std,
+                est.bias.distance_bias_rate_std,
+                est.bias.direction_bias_std,
+            ],
+        );
+        estimator.set_association(self.association());
+        estimator
+    }
+    pub fn build_fast_slam(
+        &self,
+        version: FastSlamVersion,
+        input: &Input,
+        seed_offset: u64,
+    ) -> FastSlam {
+        let est = &self.estimator;
+        FastSlam::new(
+            version,
+            est.seed + seed_offset,
+            input.time_interval,
+            input.init_pose,
+            input.radius,
+            est.particle_num,
+            self.motion_noise_pdf(),
+            est.distance_rate_std,
+            est.direction_std,
+        )
+    }
+    pub fn build_graph_slam(&self, input: &Input) -> GraphSlam {
+        let est = &self.estimator;
+        let c = &self.graph_slam;
+        GraphSlam {
+            method: match c.method {
+                GraphSlamMethodConfig::GaussNewton => GraphSlamMethod::GaussNewton,
+                GraphSlamMethodConfig::LevenbergMarquardt => GraphSlamMethod::LevenbergMarquardt,
+            },
+            max_iterations: c.max_iterations,
+            tolerance: c.tolerance,
+            time_interval: input.time_interval,
+            init_pose: input.init_pose,
+            motion_noise_pdf: self.motion_noise_pdf(),
+            distance_rate_std: est.distance_rate_std,
+            direction_std: est.direction_std,
+        }
+    }
+    pub fn build_estimators(&self, input: &Input, seed_offset: u64) -> Vec<Box<dyn Filter>> {
+        let est = &self.estimator;
+        let mut estimators: Vec<Box<dyn Filter>> = vec![];
+        for kind in est.kinds.iter() {
+            match kind.as_str() {
+                "mcl" => estimators.push(Box::new(self.build_mcl(input, seed_offset))),
+                "ekf" => estimators.push(Box::new(self.build_ekf(input))),
+                "ukf" => estimators.push(Box::new(self.build_ukf(input))),
+                "ekf_bias" => estimators.push(Box::new(self.build_bias_ekf(input))),
+                "ekf_slam" => estimators.push(Box::new(EkfSlam::new(
+                    input.time_interval,
+                    input.init_pose,
+                    input.radius,
+                    self.motion_noise_pdf(),
+                    est.distance_rate_std,
+                    est.direction_std,
+                ))),
+                "fast_slam1" => estimators.push(Box::new(self.build_fast_slam(
+                    FastSlamVersion::One,
+                    input,
+                    seed_offset,
+                ))),
+                "fast_slam2" => estimators.push(Box::new(self.build_fast_slam(
+                    FastSlamVersion::Two,
+                    input,
+                    seed_offset,
+                ))),
+                _ => unreachable!(),
+            }
+        }
+        estimators
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::BTreeMap;
+
+// 行ごとに非零要素だけを保持する疎な対称行列(下三角部分のみ保持する)
+#[derive(Debug, Clone)]
+pub struct SparseSymmetric {
+    pub n: usize,
+    pub rows: Vec<BTreeMap<usize, f64>>,
+}
+
+impl SparseSymmetric {
+    pub fn new(n: usize) -> Self {
+        Self {
+            n,
+            rows: vec![BTreeMap::new(); n],
+        }
+    }
+    // (i, j)と(j, i)の両方にvを足すのと同じ
+    pub fn add(&mut self, i: usize, j: usize, v: f64) {
+        let (i, j) = if i >= j { (i, j) } else { (j, i) };
+        *self.rows[i].entry(j).or_insert(0.0) += v;
+    }
+    pub fn get(&self, i: usize, j: usize) -> f64 {
+        let (i, j) = if i >= j { (i, j) } else { (j, i) };
+        self.rows[i].get(&j).copied().unwrap_or(0.0)
+    }
+    pub fn diagonal(&self) -> Vec<f64> {
+        (0..self.n).map(|i| self.get(i, i)).collect()
+    }
+    pub fn nnz(&self) -> usize {
+        self.rows.iter().map(|row| row.len()).sum()
+    }
+    // スカイライン(プロファイル)法によるコレスキー分解
+    // 各行の最初の非零要素から対角までの範囲だけを保持するので, 帯状の行列ではフィルインが少ない
+    pub fn cholesky(&self) -> Option<SkylineCholesky> {
+        let first: Vec<usize> = self
+            .rows
+            .iter()
+            .enumerate()
+            .map(|(i, row)| row.keys().next().copied().unwrap_or(i).min(i))
+            .collect();
+        let mut l: Vec<Vec<f64>> = Vec::with_capacity(self.n);
+        for i in 0..self.n {
+            let fi = first[i];
+            let mut row = vec![0.0; i - fi + 1];
+            for (&j, &v) in self.rows[i].iter() {
+                row[j - fi] = v;
+            }
+            for j in fi..i {
+                let fj = first[j];
+                let k0 = fi.max(fj);
+                let mut s = row[j - fi];
+                for k in k0..j {
+                    s -= row[k - fi] * l[j][k - fj];
+                }
+                row[j - fi] = s / l[j][j - fj];
+            }
+            let mut d = row[i - fi];
+            for k in fi..i {
+                d -= row[k - fi] * row[k - fi];
+            }
+            if d <= 0.0 || !d.is_finite() {
+                return None;
+            }
+            row[i - fi] = d.sqrt();
+            l.push(row);
+        }
+        Some(SkylineCholesky { first, l })
+    }
+}
+
+// A = L L^T の分解結果
+#[derive(Debug, Clone)]
+pub struct SkylineCholesky {
+    first: Vec<usize>,
+    l: Vec<Vec<f64>>,
+}
+
+impl SkylineCholesky {
+    // A x = b を解く
+    pub fn solve(&self, b: &[f64]) -> Vec<f64> {
+        let n = self.first.len();
+        // L y = b
+        let mut y = b.to_vec();
+        for i in 0..n {
+            let fi = self.first[i];
+            let s: f64 = self.l[i][..i - fi]
+                .iter()
+                .zip(y[fi..i].iter())
+                .map(|(l, y)| l * y)
+                .sum();
+            y[i] = (y[i] - s) / self.l[i][i - fi];
+        }
+        // L^T x = y
+        for i in (0..n).rev() {
+            let fi = self.first[i];
+            y[i] /= self.l[i][i - fi];
+            let xi = y[i];
+            for (y, l) in y[fi..i].iter_mut().zip(self.l[i][..i - fi].iter()) {
+                *y -= l * xi;
+            }
+        }
+        y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matrix::Matrix;
+
+    #[test]
+    fn skyline_solve_matches_dense_inverse() {
+        // 三重対角に, プロファイルの内側でフィルインを起こす要素を加えた対称正定値行列
+        let n = 6;
+        let mut a = SparseSymmetric::new(n);
+        for i in 0..n {
+            a.add(i, i, 4.0 + i as f64);
+            if i > 0 {
+                a.add(i, i - 1, -1.0);
+            }
+        }
+        a.add(5, 1, 0.5);
+        a.add(3, 0, -0.3);
+        let b = [1.0, -2.0, 0.5, 3.0, 0.0, -1.5];
+        let x = a.cholesky().unwrap().solve(&b);
+
+        let mut dense = Matrix::new(n, n);
+        for i in 0..n {
+            for j in 0..n {
+                dense[(i, j)] = a.get(i, j);
+            }
+        }
+        let expected = &dense.inverse().unwrap() * &Matrix::column(&b);
+        for (i, xi) in x.iter().enumerate() {
+            assert!((xi - expected[(i, 0)]).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn skyline_cholesky_of_indefinite_matrix_is_none() {
+        let mut a = SparseSymmetric::new(2);
+        a.add(0, 0, 1.0);
+        a.add(1, 0, 2.0);
+        a.add(1, 1, 1.0);
+        assert!(a.cholesky().is_none());
+    }
+}
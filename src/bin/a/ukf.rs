@@ -0,0 +1,312 @@
+use crate::agent::Pose;
+use crate::association::{associate, Association};
+use crate::camera::{observe_landmark, Observation};
+use crate::common::{convert_radian_in_range, Coord};
+use crate::ekf::motion_noise_cov;
+use crate::estimator::{Belief, Filter, MotionNoisePdf};
+use crate::matrix::Matrix;
+use crate::motion::state_transition;
+
+// シグマ点の広がりを決めるパラメータ(βは正規分布に最適な2)
+// α=1, κ=0なら中心のシグマ点の重みが負にならず, 共分散が半正定値に保たれる
+const ALPHA: f64 = 1.0;
+const BETA: f64 = 2.0;
+const KAPPA: f64 = 0.0;
+// 共分散が半正定値に退化してもコレスキー分解できるように対角を底上げする量
+const JITTER: f64 = 1e-12;
+
+// アンセンテッドカルマンフィルタによる自己位置推定
+// ヤコビアンを使わず, シグマ点をstate_transitionとobserve_landmarkにそのまま通す
+#[derive(Debug)]
+pub struct UkfEstimator {
+    pub time_interval: f64,
+    pub radius: f64,
+    pub mean: Pose,
+    pub cov: Matrix, // 3x3 (x, y, theta)
+    pub motion_noise_pdf: MotionNoisePdf,
+    pub distance_rate_std: f64,
+    pub direction_std: f64,
+    pub pose_records: Vec<Pose>,
+    pub cov_records: Vec<Matrix>,
+    pub association: Association,
+}
+
+impl UkfEstimator {
+    pub fn new(
+        time_interval: f64,
+        init_pose: Pose,
+        radius: f64,
+        motion_noise_pdf: MotionNoisePdf,
+        distance_rate_std: f64,
+        direction_std: f64,
+    ) -> Self {
+        let cov = Matrix::diag(&[1e-10, 1e-10, 1e-10]);
+        Self {
+            time_interval,
+            radius,
+            mean: init_pose,
+            cov: cov.clone(),
+            motion_noise_pdf,
+            distance_rate_std,
+            direction_std,
+            pose_records: vec![init_pose],
+            cov_records: vec![cov],
+            association: Association::Known,
+        }
+    }
+    pub fn set_association(&mut self, association: Association) {
+        self.association = association;
+    }
+    // 観測をランドマークに対応付ける(動作更新後の推定姿勢を使う)
+    pub fn associate(&self, observation: &[Observation], landmarks: &[Coord]) -> Vec<Observation> {
+        associate(
+            self.association,
+            &self.mean,
+            &self.cov,
+            observation,
+            landmarks,
+            self.distance_rate_std,
+            self.direction_std,
+        )
+    }
+    // 姿勢と制御指令の雑音(ν, ω)を並べた5次元の状態からシグマ点を作り, 状態遷移に通す
+    pub fn update_motion(&mut self, prev_nu: f64, prev_omega: f64) {
+        let dt = self.time_interval;
+        let m = motion_noise_cov(&self.motion_noise_pdf, prev_nu, prev_omega, dt);
+        let mut cov = Matrix::new(5, 5);
+        cov.set_block(0, 0, &self.cov);
+        cov.set_block(3, 3, &m);
+        let mean = [
+            self.mean.coord.x,
+            self.mean.coord.y,
+            self.mean.theta,
+            0.0,
+            0.0,
+        ];
+        let sigma = match SigmaPoints::new(&mean, &cov) {
+            Some(sigma) => sigma,
+            None => return,
+        };
+        let poses: Vec<[f64; 3]> = sigma
+            .points
+            .iter()
+            .map(|p| {
+                let pose = Pose {
+                    coord: Coord { x: p[0], y: p[1] },
+                    theta: p[2],
+                };
+                let pose = state_transition(dt, pose, prev_nu + p[3], prev_omega + p[4]);
+                [pose.coord.x, pose.coord.y, pose.theta]
+            })
+            .collect();
+        let mean = sigma.mean(&poses, &[2]);
+        self.cov = sigma
+            .cov(&poses, &mean, &poses, &mean, &[2], &[2])
+            .symmetrize();
+        self.mean = Pose {
+            coord: Coord {
+                x: mean[0],
+                y: mean[1],
+            },
+            theta: mean[2],
+        };
+    }
+    pub fn updater_observation(&mut self, observation: &[Observation], landmarks: &[Coord]) {
+        for obs in observation.iter() {
+            let mark = landmarks[obs.id];
+            let mean = [self.mean.coord.x, self.mean.coord.y, self.mean.theta];
+            let sigma = match SigmaPoints::new(&mean, &self.cov) {
+                Some(sigma) => sigma,
+                None => continue,
+            };
+            let poses: Vec<[f64; 3]> = sigma.points.iter().map(|p| [p[0], p[1], p[2]]).collect();
+            let zs: Vec<[f64; 2]> = poses
+                .iter()
+                .map(|p| {
+                    let pose = Pose {
+                        coord: Coord { x: p[0], y: p[1] },
+                        theta: p[2],
+                    };
+                    let z = observe_landmark(&pose, &mark, obs.id);
+                    [z.dist, z.angle]
+                })
+                .collect();
+            let z_mean = sigma.mean(&zs, &[1]);
+            let q = Matrix::diag(&[
+                (self.distance_rate_std * z_mean[0]).powf(2.0),
+                self.direction_std.powf(2.0),
+            ]);
+            let s = &sigma.cov(&zs, &z_mean, &zs, &z_mean, &[1], &[1]) + &q;
+            let t = sigma.cov(&poses, &mean, &zs, &z_mean, &[2], &[1]);
+            let s_inv = match s.inverse() {
+                Some(s_inv) => s_inv,
+                None => continue,
+            };
+            let k = &t * &s_inv;
+            let innovation = Matrix::column(&[
+                obs.dist - z_mean[0],
+                convert_radian_in_range(obs.angle - z_mean[1]),
+            ]);
+            let delta = &k * &innovation;
+            self.mean.coord.x += delta[(0, 0)];
+            self.mean.coord.y += delta[(1, 0)];
+            self.mean.theta += delta[(2, 0)];
+            self.cov = (&self.cov - &(&(&k * &s) * &k.transpose())).symmetrize();
+        }
+    }
+    pub fn record(&mut self) {
+        self.pose_records.push(self.mean);
+        self.cov_records.push(self.cov.clone());
+    }
+}
+
+impl Filter for UkfEstimator {
+    fn name(&self) -> &str {
+        "UKF"
+    }
+    fn radius(&self) -> f64 {
+        self.radius
+    }
+    fn predict(&mut self, nu: f64, omega: f64) {
+        self.update_motion(nu, omega);
+    }
+    fn update(&mut self, observation: &[Observation], landmarks: &[Coord]) {
+        let observation = self.associate(observation, landmarks);
+        self.updater_observation(&observation, landmarks);
+    }
+    fn finalize(&mut self) {
+        self.record();
+    }
+    fn pose(&self) -> Pose {
+        self.mean
+    }
+    fn estimate(&self, turn: usize) -> Pose {
+        self.pose_records[turn]
+    }
+    fn belief(&self, turn: usize) -> Belief {
+        Belief::Gaussian {
+            mean: self.pose_records[turn],
+            cov: self.cov_records[turn].clone(),
+        }
+    }
+}
+
+// 対称なシグマ点(中心と, 共分散の平方根の各列を正負に足した2n個)とその重み
+struct SigmaPoints {
+    points: Vec<Vec<f64>>,
+    mean_weights: Vec<f64>,
+    cov_weights: Vec<f64>,
+}
+
+impl SigmaPoints {
+    fn new(mean: &[f64], cov: &Matrix) -> Option<Self> {
+        let n = mean.len();
+        let lambda = ALPHA.powf(2.0) * (n as f64 + KAPPA) - n as f64;
+        let jitter = Matrix::diag(&vec![JITTER; n]);
+        let l = (&cov.scale(n as f64 + lambda) + &jitter).cholesky()?;
+        let mut points = vec![mean.to_vec()];
+        for sign in [1.0, -1.0] {
+            for j in 0..n {
+                points.push((0..n).map(|i| mean[i] + sign * l[(i, j)]).collect());
+            }
+        }
+        let w = 0.5 / (n as f64 + lambda);
+        let mut mean_weights = vec![w; 2 * n + 1];
+        let mut cov_weights = vec![w; 2 * n + 1];
+        mean_weights[0] = lambda / (n as f64 + lambda);
+        cov_weights[0] = mean_weights[0] + 1.0 - ALPHA.powf(2.0) + BETA;
+        Some(Self {
+            points,
+            mean_weights,
+            cov_weights,
+        })
+    }
+    // 変換後のシグマ点の重み付き平均
+    // 角度の成分(angles)は中心のシグマ点からの差を[-π, π)に収めて平均し, 連続した値のまま返す
+    fn mean<const N: usize>(&self, ys: &[[f64; N]], angles: &[usize]) -> [f64; N] {
+        let mut mean = [0.0; N];
+        for (k, m) in mean.iter_mut().enumerate() {
+            if angles.contains(&k) {
+                let center = ys[0][k];
+                *m = center
+                    + ys.iter()
+                        .zip(self.mean_weights.iter())
+                        .map(|(y, w)| w * convert_radian_in_range(y[k] - center))
+                        .sum::<f64>();
+            } else {
+                *m = ys
+                    .iter()
+                    .zip(self.mean_weights.iter())
+                    .map(|(y, w)| w * y[k])
+                    .sum();
+            }
+        }
+        mean
+    }
+    // 変換後のシグマ点の相互共分散 Σ w (y - y_mean)(z - z_mean)^T
+    // 角度の成分の差は[-π, π)に収める
+    fn cov<const N: usize, const M: usize>(
+        &self,
+        ys: &[[f64; N]],
+        y_mean: &[f64; N],
+        zs: &[[f64; M]],
+        z_mean: &[f64; M],
+        y_angles: &[usize],
+        z_angles: &[usize],
+    ) -> Matrix {
+        let diff = |v: f64, mean: f64, is_angle: bool| {
+            if is_angle {
+                convert_radian_in_range(v - mean)
+            } else {
+                v - mean
+            }
+        };
+        let mut cov = Matrix::new(N, M);
+        for ((y, z), w) in ys.iter().zip(zs.iter()).zip(self.cov_weights.iter()) {
+            for i in 0..N {
+                let dy = diff(y[i], y_mean[i], y_angles.contains(&i));
+                for j in 0..M {
+                    let dz = diff(z[j], z_mean[j], z_angles.contains(&j));
+                    cov[(i, j)] += w * dy * dz;
+                }
+            }
+        }
+        cov
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // y = A x + b
+    fn affine(a: &Matrix, b: &[f64; 3], x: &[f64]) -> [f64; 3] {
+        let y = &(a * &Matrix::column(x)) + &Matrix::column(b);
+        [y[(0, 0)], y[(1, 0)], y[(2, 0)]]
+    }
+
+    #[test]
+    fn sigma_points_reproduce_linear_transform() {
+        // 線形変換なら, 変換後のシグマ点の平均と共分散はA m + bとA P A^Tに一致する
+        let mean = [1.0, -2.0, 0.3];
+        let cov = Matrix::from_vec(
+            3,
+            3,
+            vec![0.5, 0.1, 0.02, 0.1, 0.3, -0.05, 0.02, -0.05, 0.1],
+        );
+        let a = Matrix::from_vec(3, 3, vec![1.0, 2.0, 0.0, -0.5, 1.0, 3.0, 0.0, 0.4, 1.5]);
+        let b = [0.5, 1.0, -1.0];
+        let sigma = SigmaPoints::new(&mean, &cov).unwrap();
+        let ys: Vec<[f64; 3]> = sigma.points.iter().map(|x| affine(&a, &b, x)).collect();
+
+        let y_mean = sigma.mean(&ys, &[]);
+        for (m, e) in y_mean.iter().zip(affine(&a, &b, &mean).iter()) {
+            assert!((m - e).abs() < 1e-12, "{:?}", y_mean);
+        }
+        let y_cov = sigma.cov(&ys, &y_mean, &ys, &y_mean, &[], &[]);
+        let expected = &(&a * &cov) * &a.transpose();
+        for (c, e) in y_cov.data.iter().zip(expected.data.iter()) {
+            assert!((c - e).abs() < 1e-9, "{:?} != {:?}", y_cov, expected);
+        }
+    }
+}
@@ -1,10 +1,16 @@
-use crate::{convert_radian_in_range, Agent, Coord, Estimator, Input, Output};
+use crate::agent::Pose;
+use crate::control::{ConstantControl, ControlPolicy};
+use crate::estimator::{Belief, Filter};
+use crate::matrix::Matrix;
+use crate::planner::Plan;
+use crate::{convert_radian_in_range, Agent, Coord, Input, Output};
 
 use eframe::egui::{
     show_tooltip_at_pointer, Align2, CentralPanel, Color32, Context, FontFamily, FontId, Id, Key,
-    Pos2, Rect, RichText, Slider, Stroke, Ui, Vec2,
+    Pos2, Rect, RichText, Shape, Slider, Stroke, Ui, Vec2, Window,
 };
 use eframe::{run_native, App, Frame, NativeOptions, Storage, Theme};
+use std::rc::Rc;
 use std::time::{Duration, Instant};
 
 const WIDTH: f32 = 800.0;
@@ -16,6 +22,80 @@ const OFFSET_HEIGHT: f32 = (HEIGHT - VIS_HEIGHT) / 2.0;
 const SPEED_MIN: usize = 1;
 const SPEED_MAX: usize = 10;
 const AGENT_COLORS: [Color32; 4] = [Color32::RED, Color32::BLUE, Color32::GREEN, Color32::BROWN];
+// 遠隔操作でキーを押している間に与える速度と角速度の初期値
+const TELEOP_NU: f64 = 0.2; // m/s
+const TELEOP_OMEGA_DEG: f64 = 30.0; // deg/s
+const ESTIMATOR_COLORS: [Color32; 4] = [
+    Color32::BLUE,
+    Color32::GOLD,
+    Color32::DARK_GREEN,
+    Color32::LIGHT_BLUE,
+];
+
+// ライブモードでウィジェットから変えるエージェントの雑音と推定器のパーティクル数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LiveSettings {
+    pub noise_per_meter: f64,
+    pub noise_std: f64,
+    pub distance_noise_rate: f64,
+    pub direction_noise: f64,
+    pub particle_num: usize,
+}
+
+impl LiveSettings {
+    // prevから変わった項目だけを走行中のエージェントと推定器に反映する
+    fn apply(&self, prev: &LiveSettings, output: &mut Output) {
+        let agent = &mut output.agents[0];
+        if (self.noise_per_meter, self.noise_std) != (prev.noise_per_meter, prev.noise_std) {
+            agent.set_motion_noise(self.noise_per_meter, self.noise_std);
+        }
+        if (self.distance_noise_rate, self.direction_noise)
+            != (prev.distance_noise_rate, prev.direction_noise)
+        {
+            agent.set_camera_noise(self.distance_noise_rate, self.direction_noise);
+        }
+        if self.particle_num != prev.particle_num {
+            for estimator in output.estimators.iter_mut() {
+                estimator.set_particle_num(self.particle_num);
+            }
+        }
+    }
+}
+
+// seed_offsetから走行開始時の状態と制御方策を作る
+pub type LiveBuild = Box<dyn Fn(u64) -> (Input, Output, Box<dyn ControlPolicy>)>;
+
+// 再生しながら1ステップずつシミュレーションを進めるライブモードの状態
+pub struct Live {
+    build: LiveBuild,
+    policy: Box<dyn ControlPolicy>, // 遠隔操作中でなければこれで制御指令を決める
+    seed_offset: u64,
+    defaults: LiveSettings, // buildで作った状態の設定
+    settings: LiveSettings,
+    teleop: bool, // 矢印キー・WASDで制御指令を与える
+    teleop_nu: f64,
+    teleop_omega: f64,
+    command: (f64, f64), // 遠隔操作で次のステップに与える(nu, omega)
+}
+
+impl Live {
+    pub fn new(build: LiveBuild, defaults: LiveSettings) -> Self {
+        Self {
+            build,
+            policy: Box::new(ConstantControl {
+                nu: 0.0,
+                omega: 0.0,
+            }),
+            seed_offset: 0,
+            defaults,
+            settings: defaults,
+            teleop: false,
+            teleop_nu: TELEOP_NU,
+            teleop_omega: TELEOP_OMEGA_DEG.to_radians(),
+            command: (0.0, 0.0),
+        }
+    }
+}
 
 pub struct Egui {
     input: Input,
@@ -23,24 +103,175 @@ pub struct Egui {
     turn: usize,
     max_turn: usize,
     checked: bool,
+    show_uncertainty: bool,
     play: bool,
     speed: usize,
     instant: Instant,
     cnt: usize,
+    live: Option<Live>,
+    plan: Option<Rc<Plan>>, // 価値反復の方策で走るときの価値関数
+    show_value: bool,
 }
 
 impl Egui {
-    fn new(input: Input, output: Output, max_turn: usize) -> Self {
+    fn new(
+        input: Input,
+        output: Output,
+        max_turn: usize,
+        live: Option<Live>,
+        plan: Option<Rc<Plan>>,
+    ) -> Self {
         Egui {
             input,
             output,
             turn: 0,
             max_turn,
             checked: true,
+            show_uncertainty: true,
             play: false,
             speed: 5,
             instant: Instant::now(),
             cnt: 0,
+            live,
+            plan,
+            show_value: true,
+        }
+    }
+    // ライブモードで走行時間の終わりまで達していなければシミュレーションを進められる
+    fn can_extend(&self) -> bool {
+        self.live.is_some() && self.max_turn < self.input.max_turn()
+    }
+    // 表示するturnを1つ進める(ライブモードで記録の末尾にいれば1ステップ走らせる)
+    // 遠隔操作中でなければシナリオの制御方策で走らせる
+    fn forward(&mut self) {
+        if self.turn == self.max_turn && self.can_extend() {
+            let (nu, omega) = match &mut self.live {
+                Some(live) if live.teleop => live.command,
+                Some(live) => live
+                    .policy
+                    .control(self.max_turn + 1, &self.output.control_estimate()),
+                None => return,
+            };
+            self.output.step(&self.input.landmarks, nu, omega);
+            self.max_turn += 1;
+        }
+        if self.turn < self.max_turn {
+            self.turn += 1;
+        }
+    }
+    // 次のシードで走行を最初からやり直す(ウィジェットで変えた設定は引き継ぐ)
+    fn reset(&mut self) {
+        if let Some(live) = &mut self.live {
+            live.seed_offset += 1;
+            let (input, mut output, policy) = (live.build)(live.seed_offset);
+            live.settings.apply(&live.defaults, &mut output);
+            live.policy = policy;
+            self.input = input;
+            self.output = output;
+            self.turn = 0;
+            self.max_turn = 0;
+        }
+    }
+    fn is_teleop(&self) -> bool {
+        self.live.as_ref().is_some_and(|live| live.teleop)
+    }
+    // 押しているキーから次のステップの制御指令を決める(離せば止まる)
+    // W/↑で前進, S/↓で後退, A/←で左旋回, D/→で右旋回
+    fn read_teleop_keys(&mut self, ctx: &Context) {
+        let live = match &mut self.live {
+            Some(live) if live.teleop => live,
+            _ => return,
+        };
+        let input = ctx.input();
+        let down = |keys: [Key; 2]| keys.iter().any(|&key| input.key_down(key));
+        let axis = |positive: bool, negative: bool| positive as i32 as f64 - negative as i32 as f64;
+        live.command = (
+            live.teleop_nu * axis(down([Key::W, Key::ArrowUp]), down([Key::S, Key::ArrowDown])),
+            live.teleop_omega
+                * axis(
+                    down([Key::A, Key::ArrowLeft]),
+                    down([Key::D, Key::ArrowRight]),
+                ),
+        );
+    }
+    fn view_live_controls(&mut self, ctx: &Context) {
+        let mut step = false;
+        let mut reset = false;
+        let has_particles = self
+            .output
+            .estimators
+            .iter()
+            .any(|e| e.particle_num(0).is_some());
+        let live = match &mut self.live {
+            Some(live) => live,
+            None => return,
+        };
+        let prev = live.settings;
+        let Live {
+            seed_offset,
+            settings: s,
+            teleop,
+            teleop_nu,
+            teleop_omega,
+            command,
+            ..
+        } = live;
+        let mut play = self.play;
+        Window::new("Live").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let label = if play { "Pause" } else { "Play" };
+                if ui.button(label).clicked() {
+                    play = !play;
+                }
+                step = ui.button("Step").clicked();
+                reset = ui.button("Reset").clicked();
+            });
+            ui.label(format!("seed offset: {}", seed_offset));
+            ui.add(Slider::new(&mut s.noise_per_meter, 0.0..=20.0).text("motion noise [1/m]"));
+            let mut noise_std = s.noise_std.to_degrees();
+            if ui
+                .add(Slider::new(&mut noise_std, 0.0..=30.0).text("motion noise std [deg]"))
+                .changed()
+            {
+                s.noise_std = noise_std.to_radians();
+            }
+            ui.add(Slider::new(&mut s.distance_noise_rate, 0.0..=0.5).text("distance noise rate"));
+            let mut direction_noise = s.direction_noise.to_degrees();
+            if ui
+                .add(Slider::new(&mut direction_noise, 0.0..=10.0).text("direction noise [deg]"))
+                .changed()
+            {
+                s.direction_noise = direction_noise.to_radians();
+            }
+            if has_particles {
+                ui.add(Slider::new(&mut s.particle_num, 10..=1000).text("particles"));
+            }
+            ui.separator();
+            ui.checkbox(teleop, "Teleop (WASD / arrow keys)");
+            if *teleop {
+                ui.add(Slider::new(teleop_nu, 0.0..=1.0).text("nu [m/s]"));
+                let mut omega = teleop_omega.to_degrees();
+                if ui
+                    .add(Slider::new(&mut omega, 0.0..=180.0).text("omega [deg/s]"))
+                    .changed()
+                {
+                    *teleop_omega = omega.to_radians();
+                }
+                ui.label(format!(
+                    "command: nu {:+.2} m/s, omega {:+.1} deg/s",
+                    command.0,
+                    command.1.to_degrees()
+                ));
+            }
+        });
+        live.settings.apply(&prev, &mut self.output);
+        self.play = play;
+        if step {
+            self.play = false;
+            self.forward();
+        }
+        if reset {
+            self.reset();
         }
     }
 }
@@ -49,13 +280,13 @@ impl App for Egui {
     fn save(&mut self, _storage: &mut dyn Storage) {}
     fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
         ctx.request_repaint_after(Duration::from_millis(5));
+        self.read_teleop_keys(ctx);
         if self.instant.elapsed() >= Duration::from_millis(10) {
             self.cnt += 1;
-            if self.cnt % (SPEED_MIN + SPEED_MAX - self.speed) == 0
-                && self.play
-                && self.turn < self.max_turn
-            {
-                self.turn += 1;
+            // is_multiple_ofはRust 1.87以降にしかないので剰余で判定する
+            #[allow(clippy::manual_is_multiple_of)]
+            if self.cnt % (SPEED_MIN + SPEED_MAX - self.speed) == 0 && self.play {
+                self.forward();
             }
             self.instant = Instant::now();
         }
@@ -65,12 +296,25 @@ impl App for Egui {
             let height = self.input.height;
             let d = VIS_WIDTH / (height.max(width)) as f32;
 
+            if let Some(plan) = &self.plan {
+                view_plan(ui, &self.input, d, plan, self.show_value);
+            }
             view_world(ui, &self.input, d);
             for (id, &coord) in self.input.landmarks.iter().enumerate() {
                 view_landmark(ui, &self.input, d, id, coord);
             }
 
-            view_estimator(ui, &self.input, d, &self.output.estimator, self.turn);
+            for (idx, estimator) in self.output.estimators.iter().enumerate() {
+                view_estimator(
+                    ui,
+                    &self.input,
+                    d,
+                    estimator.as_ref(),
+                    self.turn,
+                    ESTIMATOR_COLORS[idx % ESTIMATOR_COLORS.len()],
+                    self.show_uncertainty,
+                );
+            }
 
             for (idx, agent) in self.output.agents.iter().enumerate() {
                 view_agent(ui, &self.input, d, agent, self.turn, AGENT_COLORS[idx]);
@@ -84,24 +328,90 @@ impl App for Egui {
                 ui.label(RichText::new("Speed: ").size(20.0));
                 ui.add(Slider::new(&mut self.speed, SPEED_MIN..=SPEED_MAX));
             });
+            for estimator in self.output.estimators.iter() {
+                if let Some(n) = estimator.particle_num(self.turn) {
+                    let ess = estimator
+                        .effective_sample_size(self.turn)
+                        .map(|ess| format!(" (ESS: {:.1})", ess))
+                        .unwrap_or_default();
+                    ui.label(
+                        RichText::new(format!("{} particles: {}{}", estimator.name(), n, ess))
+                            .size(20.0),
+                    );
+                }
+                if let Some(b) = estimator.bias_estimate(self.turn) {
+                    ui.label(
+                        RichText::new(format!(
+                            "{} bias: nu x{:.3}, omega x{:.3}, distance {:+.3}, direction {:+.2}deg",
+                            estimator.name(),
+                            b.mean[0],
+                            b.mean[1],
+                            b.mean[2],
+                            b.mean[3].to_degrees()
+                        ))
+                        .size(20.0),
+                    );
+                }
+                // 直近のリセット
+                if let Some((turn, event)) = (0..=self.turn)
+                    .rev()
+                    .find_map(|t| estimator.reset_event(t).map(|e| (t, e)))
+                {
+                    let color = if turn == self.turn {
+                        Color32::RED
+                    } else {
+                        Color32::GRAY
+                    };
+                    ui.label(
+                        RichText::new(format!("{} {} at turn {}", estimator.name(), event, turn))
+                            .size(20.0)
+                            .color(color),
+                    );
+                }
+            }
+            ui.checkbox(
+                &mut self.show_uncertainty,
+                RichText::new("Uncertainty (1/2/3σ)").size(20.0),
+            );
+            if self.plan.is_some() {
+                ui.checkbox(
+                    &mut self.show_value,
+                    RichText::new("Value function").size(20.0),
+                );
+            }
 
             if ctx.input().key_released(Key::Space) {
                 self.play = !self.play;
             };
-            if self.turn == self.max_turn {
+            if self.turn == self.max_turn && !self.can_extend() {
                 self.play = false;
             }
-            if ctx.input().key_pressed(Key::ArrowRight) && self.turn < self.max_turn {
-                self.turn += 1;
+            // 遠隔操作中は矢印キーを操縦に使う
+            if ctx.input().key_pressed(Key::ArrowRight) && !self.is_teleop() {
+                self.forward();
             };
-            if ctx.input().key_pressed(Key::ArrowLeft) && self.turn > 0 {
+            if ctx.input().key_pressed(Key::ArrowLeft) && !self.is_teleop() && self.turn > 0 {
                 self.turn -= 1;
             };
         });
+        self.view_live_controls(ctx);
     }
 }
 
-pub fn visualizer(input: Input, output: Output, max_turn: usize) {
+pub fn visualizer(input: Input, output: Output, max_turn: usize, plan: Option<Rc<Plan>>) {
+    let options = NativeOptions {
+        initial_window_size: Some((WIDTH, HEIGHT).into()),
+        initial_window_pos: Some(Pos2 { x: 100.0, y: 100.0 }),
+        resizable: false,
+        default_theme: Theme::Light,
+        ..NativeOptions::default()
+    };
+    let gui = Egui::new(input, output, max_turn, None, plan);
+    run_native("visualizer", options, Box::new(|_cc| Box::new(gui)));
+}
+
+// 記録済みの走行ではなく, 再生に合わせてシミュレーションを進める
+pub fn live_visualizer(mut live: Live, plan: Option<Rc<Plan>>) {
     let options = NativeOptions {
         initial_window_size: Some((WIDTH, HEIGHT).into()),
         initial_window_pos: Some(Pos2 { x: 100.0, y: 100.0 }),
@@ -109,7 +419,9 @@ pub fn visualizer(input: Input, output: Output, max_turn: usize) {
         default_theme: Theme::Light,
         ..NativeOptions::default()
     };
-    let gui = Egui::new(input, output, max_turn);
+    let (input, output, policy) = (live.build)(live.seed_offset);
+    live.policy = policy;
+    let gui = Egui::new(input, output, 0, Some(live), plan);
     run_native("visualizer", options, Box::new(|_cc| Box::new(gui)));
 }
 // 0 <= val <= 1
@@ -202,6 +514,31 @@ pub fn circle(
         },
     }
 }
+pub fn polyline(ui: &mut Ui, points: &[Pos2], color: Color32, stroke_width: f32) {
+    let points = points
+        .iter()
+        .map(|p| Pos2 {
+            x: p.x + OFFSET_WIDTH,
+            y: p.y + OFFSET_HEIGHT,
+        })
+        .collect();
+    let stroke = Stroke {
+        width: stroke_width,
+        color,
+    };
+    ui.painter().add(Shape::closed_line(points, stroke));
+}
+pub fn polygon(ui: &mut Ui, points: &[Pos2], fill_color: Color32) {
+    let points = points
+        .iter()
+        .map(|p| Pos2 {
+            x: p.x + OFFSET_WIDTH,
+            y: p.y + OFFSET_HEIGHT,
+        })
+        .collect();
+    ui.painter()
+        .add(Shape::convex_polygon(points, fill_color, Stroke::none()));
+}
 pub fn arrow(ui: &mut Ui, mut origin: Pos2, vec: Vec2, stroke_color: Color32, stroke_width: f32) {
     origin.x += OFFSET_WIDTH;
     origin.y += OFFSET_HEIGHT;
@@ -211,6 +548,68 @@ pub fn arrow(ui: &mut Ui, mut origin: Pos2, vec: Vec2, stroke_color: Color32, st
     };
     ui.painter().arrow(origin, vec, stroke);
 }
+// 価値反復の水たまりとゴールを描き, show_valueなら価値関数をヒートマップで重ねる
+// 価値は向きについて最大のものを, ゴールに近い(価値が高い)ほど赤く表示する
+pub fn view_plan(ui: &mut Ui, input: &Input, d: f32, plan: &Plan, show_value: bool) {
+    let x_center = d * input.width as f32 / 2.0;
+    let y_center = d * input.height as f32 / 2.0;
+    let to_pos = |coord: Coord| Pos2 {
+        x: x_center + d * coord.x as f32,
+        y: y_center + d * (-coord.y) as f32,
+    };
+    let grid = &plan.planner.grid;
+    if show_value {
+        let values: Vec<f64> = (0..grid.ny)
+            .flat_map(|iy| (0..grid.nx).map(move |ix| (ix, iy)))
+            .map(|(ix, iy)| plan.max_value(ix, iy))
+            .collect();
+        let min_value = values
+            .iter()
+            .copied()
+            .filter(|v| v.is_finite())
+            .fold(0.0, f64::min);
+        let half = grid.xy_bin / 2.0;
+        for iy in 0..grid.ny {
+            for ix in 0..grid.nx {
+                let value = values[iy * grid.nx + ix];
+                let ratio = if min_value < 0.0 && value.is_finite() {
+                    1.0 - value / min_value
+                } else {
+                    0.0
+                };
+                let color = color32(ratio as f32);
+                let center = grid.center(ix, iy);
+                ui.painter().rect_filled(
+                    Rect {
+                        min: to_pos(Coord::new(center.x - half, center.y + half))
+                            + Vec2::new(OFFSET_WIDTH, OFFSET_HEIGHT),
+                        max: to_pos(Coord::new(center.x + half, center.y - half))
+                            + Vec2::new(OFFSET_WIDTH, OFFSET_HEIGHT),
+                    },
+                    0.0,
+                    Color32::from_rgba_unmultiplied(color.r(), color.g(), color.b(), 110),
+                );
+            }
+        }
+    }
+    for puddle in plan.planner.puddles.iter() {
+        rect(
+            ui,
+            to_pos(Coord::new(puddle.lower_left.x, puddle.upper_right.y)),
+            to_pos(Coord::new(puddle.upper_right.x, puddle.lower_left.y)),
+            Color32::from_rgba_unmultiplied(30, 80, 255, 40),
+            Color32::from_rgb(30, 80, 255),
+        );
+    }
+    let goal = &plan.planner.goal;
+    circle(
+        ui,
+        to_pos(goal.coord),
+        d * goal.radius as f32,
+        Color32::TRANSPARENT,
+        Color32::DARK_GREEN,
+    );
+}
 pub fn view_world(ui: &mut Ui, input: &Input, d: f32) {
     let view_top_left_pos = Pos2 { x: 0.0, y: 0.0 };
     let view_bottom_right_pos = Pos2 {
@@ -365,11 +764,7 @@ pub fn view_agent(ui: &mut Ui, input: &Input, d: f32, agent: &Agent, turn: usize
 
     // エージェント軌跡描画
     let trajectory_num = 200;
-    let trajectory_st = if turn <= trajectory_num {
-        0
-    } else {
-        turn - trajectory_num
-    };
+    let trajectory_st = turn.saturating_sub(trajectory_num);
     for i in trajectory_st..turn {
         let pos = Pos2 {
             x: x_center + d * agent.pose_records[i].coord.x as f32,
@@ -415,12 +810,19 @@ pub fn view_landmark(ui: &mut Ui, input: &Input, d: f32, id: usize, coord: Coord
         }
     }
 }
-pub fn view_estimator(ui: &mut Ui, input: &Input, d: f32, estimator: &Estimator, turn: usize) {
+pub fn view_estimator(
+    ui: &mut Ui,
+    input: &Input,
+    d: f32,
+    estimator: &dyn Filter,
+    turn: usize,
+    color: Color32,
+    show_uncertainty: bool,
+) {
     let x_center = d * input.width as f32 / 2.0;
     let y_center = d * input.height as f32 / 2.0;
-    let size = estimator.radius as f32 * d;
-
-    for pose in estimator.pose_records[turn].iter() {
+    let size = estimator.radius() as f32 * d;
+    let pose_arrow = |ui: &mut Ui, pose: &Pose, color: Color32| {
         let origin = Pos2 {
             x: x_center + d * pose.coord.x as f32,
             y: y_center + d * (-pose.coord.y) as f32,
@@ -429,17 +831,133 @@ pub fn view_estimator(ui: &mut Ui, input: &Input, d: f32, estimator: &Estimator,
             x: size * pose.theta.cos() as f32,
             y: -size * pose.theta.sin() as f32,
         };
-        arrow(ui, origin, vec, Color32::BLUE, 2.0);
-    }
-
-    let best_pose = estimator.pose_records[turn][estimator.best_weight_records[turn]];
-    let origin = Pos2 {
-        x: x_center + d * best_pose.coord.x as f32,
-        y: y_center + d * (-best_pose.coord.y) as f32,
+        arrow(ui, origin, vec, color, 2.0);
     };
-    let vec = Vec2 {
-        x: size * best_pose.theta.cos() as f32,
-        y: -size * best_pose.theta.sin() as f32,
+
+    view_landmark_estimates(ui, input, d, estimator, turn, color, show_uncertainty);
+    match estimator.belief(turn) {
+        Belief::Particles { poses, mean, cov } => {
+            for pose in poses.iter() {
+                pose_arrow(ui, pose, color);
+            }
+            pose_arrow(ui, &mean, Color32::GREEN);
+            if show_uncertainty {
+                view_uncertainty(ui, input, d, &mean, &cov, Color32::GREEN);
+            }
+        }
+        Belief::Gaussian { mean, cov } => {
+            pose_arrow(ui, &mean, color);
+            if show_uncertainty {
+                view_uncertainty(ui, input, d, &mean, &cov, color);
+            }
+        }
+    }
+}
+// 共分散行列の左上2x2成分によるnσ誤差楕円
+pub fn view_error_ellipse(
+    ui: &mut Ui,
+    d: f32,
+    center_pos: Pos2,
+    cov: &Matrix,
+    n: f64,
+    color: Color32,
+) {
+    let segment_num = 64;
+    // 共分散行列のx-y成分の固有値・固有ベクトルから楕円の軸を求める
+    let (a, b, c) = (cov[(0, 0)], cov[(0, 1)], cov[(1, 1)]);
+    let r = (((a - c) / 2.0).powf(2.0) + b * b).sqrt();
+    let major = ((a + c) / 2.0 + r).max(0.0).sqrt();
+    let minor = ((a + c) / 2.0 - r).max(0.0).sqrt();
+    let angle = 0.5 * (2.0 * b).atan2(a - c);
+    let points: Vec<Pos2> = (0..segment_num)
+        .map(|i| {
+            let t = 2.0 * std::f64::consts::PI * i as f64 / segment_num as f64;
+            let (u, v) = (n * major * t.cos(), n * minor * t.sin());
+            let x = u * angle.cos() - v * angle.sin();
+            let y = u * angle.sin() + v * angle.cos();
+            Pos2 {
+                x: center_pos.x + d * x as f32,
+                y: center_pos.y - d * y as f32,
+            }
+        })
+        .collect();
+    polyline(ui, &points, color, 1.5);
+}
+// SLAMで推定したランドマークと3σ誤差楕円
+pub fn view_landmark_estimates(
+    ui: &mut Ui,
+    input: &Input,
+    d: f32,
+    estimator: &dyn Filter,
+    turn: usize,
+    color: Color32,
+    show_uncertainty: bool,
+) {
+    let x_center = d * input.width as f32 / 2.0;
+    let y_center = d * input.height as f32 / 2.0;
+    for mark in estimator.landmark_estimates(turn).iter() {
+        let center_pos = Pos2 {
+            x: x_center + d * mark.coord.x as f32,
+            y: y_center + d * (-mark.coord.y) as f32,
+        };
+        let rect = circle(ui, center_pos, 3.0, color, color);
+        if show_uncertainty {
+            view_error_ellipse(ui, d, center_pos, &mark.cov, 3.0, color);
+        }
+        let hover_pos = ui.input().pointer.hover_pos();
+        if let Some(hover_pos) = hover_pos {
+            if rect.contains(hover_pos) {
+                show_tooltip_at_pointer(ui.ctx(), Id::new("hover tooltip"), |ui| {
+                    ui.label(format!(
+                        "{} id{}: (x, y) = ({:.2}, {:.2})",
+                        estimator.name(),
+                        mark.id,
+                        mark.coord.x,
+                        mark.coord.y
+                    ));
+                });
+            }
+        }
+    }
+}
+// x-y平面の1/2/3σ誤差楕円と向きの1/2/3σの扇形
+pub fn view_uncertainty(
+    ui: &mut Ui,
+    input: &Input,
+    d: f32,
+    mean: &Pose,
+    cov: &Matrix,
+    color: Color32,
+) {
+    let x_center = d * input.width as f32 / 2.0;
+    let y_center = d * input.height as f32 / 2.0;
+    let center_pos = Pos2 {
+        x: x_center + d * mean.coord.x as f32,
+        y: y_center + d * (-mean.coord.y) as f32,
     };
-    arrow(ui, origin, vec, Color32::GREEN, 2.0);
+    let segment_num = 64;
+    for n in 1..=3 {
+        view_error_ellipse(ui, d, center_pos, cov, n as f64, color);
+    }
+
+    // 向きの不確かさは半透明の扇形を重ねて描く
+    // 扇形は180°を超えると凸でなくなるため三角形に分割する
+    let theta_std = cov[(2, 2)].max(0.0).sqrt();
+    let wedge_radius = 0.5 * d as f64;
+    let fill = Color32::from_rgba_unmultiplied(color.r(), color.g(), color.b(), 40);
+    for n in 1..=3 {
+        let half = (n as f64 * theta_std).min(std::f64::consts::PI);
+        let arc: Vec<Pos2> = (0..=segment_num)
+            .map(|i| {
+                let t = mean.theta - half + 2.0 * half * i as f64 / segment_num as f64;
+                Pos2 {
+                    x: center_pos.x + (wedge_radius * t.cos()) as f32,
+                    y: center_pos.y - (wedge_radius * t.sin()) as f32,
+                }
+            })
+            .collect();
+        for w in arc.windows(2) {
+            polygon(ui, &[center_pos, w[0], w[1]], fill);
+        }
+    }
 }
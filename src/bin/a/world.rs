@@ -0,0 +1,46 @@
+use crate::agent::{Agent, Pose};
+use crate::common::Coord;
+use crate::estimator::Filter;
+
+// シミュレーションの設定(ビジュアライザとログの書き出し・再生で共有する)
+pub struct Input {
+    pub time_span: f64,
+    pub time_interval: f64,
+    pub height: usize,
+    pub width: usize,
+    pub landmarks: Vec<Coord>,
+    pub init_pose: Pose,
+    pub radius: f64,
+    pub nu: f64,
+    pub omega: f64,
+}
+
+impl Input {
+    pub fn max_turn(&self) -> usize {
+        (self.time_span / self.time_interval) as usize
+    }
+}
+
+// シミュレーションの結果
+pub struct Output {
+    pub agents: Vec<Agent>,
+    pub estimators: Vec<Box<dyn Filter>>,
+}
+
+impl Output {
+    // 閉ループ制御に使う最初の推定器の推定姿勢
+    // 推定器がなければ初期姿勢を返す(予定表どおりに走る方策は推定姿勢を使わない)
+    pub fn control_estimate(&self) -> Pose {
+        match self.estimators.first() {
+            Some(estimator) => estimator.pose(),
+            None => self.agents[0].pose_records[0],
+        }
+    }
+    // 制御指令(nu, omega)でエージェントを1ステップ動かし, 各推定器も同じ指令と観測で更新する
+    pub fn step(&mut self, landmarks: &[Coord], nu: f64, omega: f64) {
+        let observation = self.agents[0].action(nu, omega, landmarks);
+        for estimator in self.estimators.iter_mut() {
+            estimator.decision(nu, omega, &observation, landmarks);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+#![allow(dead_code)]
+
+// aの--exportで書き出したJSON Linesのログを読み込み, シミュレーションし直さずにビジュアライザで再生する
+#[path = "../a/agent.rs"]
+mod agent;
+#[path = "../a/association.rs"]
+mod association;
+#[path = "../a/camera.rs"]
+mod camera;
+#[path = "../a/common.rs"]
+mod common;
+#[path = "../a/control.rs"]
+mod control;
+#[path = "../a/ekf.rs"]
+mod ekf;
+#[path = "../a/estimator.rs"]
+mod estimator;
+#[path = "../a/likelihood.rs"]
+mod likelihood;
+#[path = "../a/log.rs"]
+mod log;
+#[path = "../a/matrix.rs"]
+mod matrix;
+#[path = "../a/motion.rs"]
+mod motion;
+#[path = "../a/normal.rs"]
+mod normal;
+#[path = "../a/planner.rs"]
+mod planner;
+#[path = "../a/reset.rs"]
+mod reset;
+#[path = "../a/vis.rs"]
+mod vis;
+#[path = "../a/world.rs"]
+mod world;
+
+use agent::Agent;
+use common::{convert_radian_in_range, Coord};
+use world::{Input, Output};
+
+const USAGE: &str = "usage: replay LOG.jsonl";
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(path) if !path.starts_with("--") => path,
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    };
+    let (input, output) = log::load(&path).unwrap_or_else(|e| {
+        eprintln!("invalid log: {}\n{}", e, USAGE);
+        std::process::exit(1);
+    });
+    let max_turn = output.agents[0].pose_records.len() - 1;
+    println!(
+        "{}: {} turns, estimators: {}",
+        path,
+        max_turn,
+        output
+            .estimators
+            .iter()
+            .map(|e| e.name().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    #[cfg(feature = "local")]
+    vis::visualizer(input, output, max_turn, None);
+    #[cfg(not(feature = "local"))]
+    let _ = (input, output, max_turn);
+}